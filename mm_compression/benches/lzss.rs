@@ -0,0 +1,157 @@
+//! Compares LZSS decoder with the previous implementation, which
+//! read input bit by bit with `bitstream_io` and returned at most one
+//! token per `read` call
+
+use std::io::Read;
+
+use bitstream_io::{BigEndian, BitRead, BitReader};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use mm_compression::compression;
+
+const WINDOW_SIZE: usize = 0x1000;
+
+struct BitwiseReader<R: Read> {
+    bit_reader: BitReader<R, BigEndian>,
+    window: [u8; WINDOW_SIZE],
+    window_pointer: usize,
+    output_pointer: usize,
+    output_size: usize,
+    bytes_outputted: usize,
+}
+
+impl<R: Read> BitwiseReader<R> {
+    fn flush_output_buffer(&mut self, out_buf: &mut [u8]) -> std::io::Result<usize> {
+        debug_assert!(self.output_size > 0);
+        let mut outputted_size: usize = 0;
+        let mut out = out_buf.iter_mut();
+
+        while self.output_size > 0 {
+            if let Some(out_byte) = out.next() {
+                let value = self.window[self.output_pointer];
+                *out_byte = value;
+                self.write_to_window(value);
+
+                self.output_pointer += 1;
+                self.output_pointer %= WINDOW_SIZE;
+                self.output_size -= 1;
+                self.bytes_outputted += 1;
+                outputted_size += 1;
+            } else {
+                break;
+            }
+        }
+        Ok(outputted_size)
+    }
+
+    fn write_to_window(&mut self, value: u8) {
+        self.window[self.window_pointer] = value;
+        self.window_pointer += 1;
+        self.window_pointer %= WINDOW_SIZE;
+    }
+}
+
+impl<R: Read> Read for BitwiseReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.output_size > 0 {
+            self.flush_output_buffer(buf)
+        } else {
+            match self.bit_reader.read_bit()? {
+                true => {
+                    self.bit_reader.read_bytes(&mut buf[0..1])?;
+                    self.write_to_window(buf[0]);
+                    self.bytes_outputted += 1;
+                    Ok(1)
+                }
+                false => {
+                    let offset: i32 = self.bit_reader.read(12)?;
+                    let size: i32 = self.bit_reader.read(4)?;
+                    self.output_pointer = offset as usize;
+                    self.output_size = (size as usize) + 2;
+                    self.flush_output_buffer(buf)
+                }
+            }
+        }
+    }
+}
+
+fn bitwise_decompress<R>(source: R) -> BitwiseReader<R>
+where
+    R: Read,
+{
+    let bit_reader = BitReader::new(source);
+    BitwiseReader {
+        bit_reader,
+        window: [0; WINDOW_SIZE],
+        window_pointer: 1,
+        output_pointer: 0,
+        output_size: 0,
+        bytes_outputted: 0,
+    }
+}
+
+/// Map-like data: repeated records with small variations
+fn synthetic_structured(size: usize) -> Vec<u8> {
+    (0..size)
+        .map(|i| match i % 12 {
+            0 => (i / 12 % 7) as u8,
+            1 => (i / 240 % 3) as u8,
+            4 => 0xff,
+            _ => 0,
+        })
+        .collect()
+}
+
+/// Poorly compressible data, mostly literals
+fn synthetic_noise(size: usize) -> Vec<u8> {
+    let mut state: u32 = 0x1234_5678;
+    (0..size)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            (state >> 24) as u8
+        })
+        .collect()
+}
+
+fn read_all<R: Read>(reader: R, size: usize) -> Vec<u8> {
+    let mut output = Vec::with_capacity(size);
+    reader.take(size as u64).read_to_end(&mut output).unwrap();
+    output
+}
+
+fn bench_decompress(c: &mut Criterion) {
+    let size = 256 * 1024;
+    let mut group = c.benchmark_group("lzss_decompress");
+    group.throughput(Throughput::Bytes(size as u64));
+
+    for (name, data) in [
+        ("structured", synthetic_structured(size)),
+        ("noise", synthetic_noise(size)),
+    ] {
+        let compressed = compression::compress(&data);
+        assert_eq!(
+            data,
+            read_all(compression::decompress(&compressed[..]), size)
+        );
+        assert_eq!(data, read_all(bitwise_decompress(&compressed[..]), size));
+
+        group.bench_with_input(
+            BenchmarkId::new("current", name),
+            &compressed,
+            |b, input| b.iter(|| read_all(compression::decompress(&input[..]), size)),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("bitwise", name),
+            &compressed,
+            |b, input| b.iter(|| read_all(bitwise_decompress(&input[..]), size)),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_decompress);
+criterion_main!(benches);
@@ -0,0 +1,197 @@
+//! Checksum stored in headers of obfuscated files
+//!
+//! Data is split into little-endian 32-bit words, which are
+//! alternately XORed and added to the checksum, starting with XOR.
+//! Incomplete word at the end of data is not included.
+
+use core::convert::TryInto;
+#[cfg(feature = "std")]
+use std::io::{self, Read, Write};
+
+/// Incrementally computed checksum
+///
+/// ```
+/// use mm_compression::Checksum;
+///
+/// let mut checksum = Checksum::new();
+/// checksum.update(&[1, 0, 0]);
+/// checksum.update(&[0, 2, 0, 0, 0]);
+/// assert_eq!(3, checksum.finish());
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct Checksum {
+    value: u32,
+    odd: bool,
+    current_word: [u8; 4],
+    current_word_fill: usize,
+}
+
+impl Checksum {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checksum of whole `data`
+    pub fn of(data: &[u8]) -> u32 {
+        let mut checksum = Self::new();
+        checksum.update(data);
+        checksum.finish()
+    }
+
+    fn add_word(&mut self, word: u32) {
+        if self.odd {
+            self.value = self.value.wrapping_add(word);
+        } else {
+            self.value ^= word;
+        }
+        self.odd = !self.odd;
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        if self.current_word_fill > 0 {
+            let size = data.len().min(4 - self.current_word_fill);
+            self.current_word[self.current_word_fill..self.current_word_fill + size]
+                .copy_from_slice(&data[..size]);
+            self.current_word_fill += size;
+            data = &data[size..];
+            if self.current_word_fill < 4 {
+                return;
+            }
+            self.add_word(u32::from_le_bytes(self.current_word));
+            self.current_word_fill = 0;
+        }
+
+        let chunks = data.chunks_exact(4);
+        let remainder = chunks.remainder();
+        for chunk in chunks {
+            self.add_word(u32::from_le_bytes(chunk.try_into().unwrap()));
+        }
+        self.current_word[..remainder.len()].copy_from_slice(remainder);
+        self.current_word_fill = remainder.len();
+    }
+
+    /// Checksum of complete words passed to [Checksum::update] so far
+    pub fn finish(&self) -> u32 {
+        self.value
+    }
+}
+
+/// Reader that calculates checksum of data read through it
+#[cfg(feature = "std")]
+pub struct ChecksummingReader<R: Read> {
+    reader: R,
+    checksum: Checksum,
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> ChecksummingReader<R> {
+    pub fn new(reader: R) -> Self {
+        ChecksummingReader {
+            reader,
+            checksum: Checksum::new(),
+        }
+    }
+
+    /// Checksum of data read so far
+    pub fn checksum(&self) -> u32 {
+        self.checksum.finish()
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> Read for ChecksummingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = self.reader.read(buf)?;
+        self.checksum.update(&buf[..size]);
+        Ok(size)
+    }
+}
+
+/// Writer that calculates checksum of data written through it
+#[cfg(feature = "std")]
+pub struct ChecksummingWriter<W: Write> {
+    writer: W,
+    checksum: Checksum,
+}
+
+#[cfg(feature = "std")]
+impl<W: Write> ChecksummingWriter<W> {
+    pub fn new(writer: W) -> Self {
+        ChecksummingWriter {
+            writer,
+            checksum: Checksum::new(),
+        }
+    }
+
+    /// Checksum of data written so far
+    pub fn checksum(&self) -> u32 {
+        self.checksum.finish()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: Write> Write for ChecksummingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let size = self.writer.write(buf)?;
+        self.checksum.update(&buf[..size]);
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_checksum() {
+        let data = [
+            1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 9,
+        ];
+        // ((0 ^ 1) + 2) ^ 3 + 0xffffffff, trailing byte ignored
+        assert_eq!(0xffff_ffff, Checksum::of(&data));
+        assert_eq!(0, Checksum::of(&[]));
+        assert_eq!(3, Checksum::of(&data[..8]));
+    }
+
+    #[test]
+    fn test_checksum_in_pieces() {
+        let data: Vec<u8> = (0..1001u32).map(|i| (i * 7 % 256) as u8).collect();
+        let expected = Checksum::of(&data);
+        for piece_size in [1, 2, 3, 5, 100] {
+            let mut checksum = Checksum::new();
+            for piece in data.chunks(piece_size) {
+                checksum.update(piece);
+            }
+            assert_eq!(expected, checksum.finish(), "pieces of {}", piece_size);
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_checksumming_reader_and_writer() {
+        let data: Vec<u8> = (0..1001u32).map(|i| (i * 7 % 256) as u8).collect();
+        let mut reader = ChecksummingReader::new(&data[..]);
+        let mut writer = ChecksummingWriter::new(Vec::new());
+        io::copy(&mut reader, &mut writer).unwrap();
+        assert_eq!(Checksum::of(&data), reader.checksum());
+        assert_eq!(Checksum::of(&data), writer.checksum());
+        assert_eq!(data, writer.into_inner());
+    }
+}
@@ -1,95 +1,1087 @@
-//! Decompression support for Magic & Mayhem
+//! Compression and decompression support for Magic & Mayhem
 //!
 //! Magic & Mayhem uses a variant of LZSS compression algorithm with
 //! 12 bits window offset, 4 bits window length, '0' bit value for
 //! window reference and big-endian encoding for offset and length.
 //!
 //! Compressed files have header (see [mm_compression]).
+//!
+//! Readers require `std` feature, [decompress_slice] is available
+//! without it.
 
-use std::io::Read;
+use alloc::boxed::Box;
+use alloc::{vec, vec::Vec};
+use core::fmt;
+#[cfg(feature = "std")]
+use std::io::{self, BufReader, Read};
 
-use bitstream_io::{BigEndian, BitRead, BitReader};
+use crate::DecompressError;
 
 const WINDOW_SIZE: usize = 0x1000;
+const MIN_MATCH: usize = 2;
+const MAX_MATCH: usize = 0xf + MIN_MATCH;
 
-pub struct CompressedReader<R: Read> {
-    bit_reader: BitReader<R, BigEndian>,
-    window: [u8; WINDOW_SIZE],
+const WINDOW_MASK: usize = WINDOW_SIZE - 1;
+#[cfg(feature = "std")]
+const INPUT_BUFFER_SIZE: usize = 0x1000;
+/// Bits needed to decode the longest token: flag, offset and length
+const MAX_TOKEN_BITS: u32 = 1 + 12 + 4;
+
+/// Buffered source of compressed bytes, any [io::BufRead] with `std`
+/// feature and byte slices without it
+trait Input {
+    /// Available bytes, empty at the end of input
+    fn fill(&mut self) -> Result<&[u8], DecompressError>;
+    fn consume(&mut self, count: usize);
+}
+
+#[cfg(feature = "std")]
+impl<B: io::BufRead> Input for B {
+    fn fill(&mut self) -> Result<&[u8], DecompressError> {
+        Ok(self.fill_buf()?)
+    }
+
+    fn consume(&mut self, count: usize) {
+        io::BufRead::consume(self, count)
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl Input for &[u8] {
+    fn fill(&mut self) -> Result<&[u8], DecompressError> {
+        Ok(self)
+    }
+
+    fn consume(&mut self, count: usize) {
+        *self = &self[count..];
+    }
+}
+
+/// Where in compressed stream decompression failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamContext {
+    /// Offset in bits from the start of compressed stream of the token
+    /// that couldn't be decoded or was malformed
+    pub bit_offset: u64,
+    /// Bytes of output produced before that token
+    pub produced: u64,
+    /// Expected size of output, if known
+    pub unpacked_size: Option<u64>,
+    /// Last token decoded before or at `bit_offset`
+    pub last_token: Option<Token>,
+}
+
+impl fmt::Display for StreamContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "at bit {} after {} ", self.bit_offset, self.produced)?;
+        if let Some(unpacked_size) = self.unpacked_size {
+            write!(f, "of {} ", unpacked_size)?;
+        }
+        write!(f, "bytes, last token: ")?;
+        match self.last_token {
+            Some(token) => write!(f, "{}", token),
+            None => write!(f, "none"),
+        }
+    }
+}
+
+/// LZSS decoding state over buffered input
+///
+/// Input is moved into 64-bit bit buffer, each `read` call decodes as
+/// many tokens as fit into the caller's buffer.
+struct LzssDecoder<I: Input> {
+    input: I,
+    /// Bytes moved from `input` to `bits` so far
+    input_consumed: u64,
+    unpacked_size: Option<u64>,
+    produced: u64,
+    last_token: Option<Token>,
+    /// Not yet decoded bits, aligned to most significant bit
+    bits: u64,
+    bits_count: u32,
+    window: Box<[u8; WINDOW_SIZE]>,
     window_pointer: usize,
-    output_pointer: usize,
-    output_size: usize,
-    bytes_outputted: usize,
+    /// Window position of the rest of current window reference
+    copy_pointer: usize,
+    copy_size: usize,
 }
 
-impl<R: Read> CompressedReader<R> {
-    fn flush_output_buffer(&mut self, out_buf: &mut [u8]) -> std::io::Result<usize> {
-        debug_assert!(self.output_size > 0);
-        let mut outputted_size: usize = 0;
-        let mut out = out_buf.iter_mut();
-
-        while self.output_size > 0 {
-            if let Some(out_byte) = out.next() {
-                let value = self.window[self.output_pointer];
-                *out_byte = value;
-                self.write_to_window(value);
-
-                self.output_pointer += 1;
-                self.output_pointer %= WINDOW_SIZE;
-                self.output_size -= 1;
-                self.bytes_outputted += 1;
-                outputted_size += 1;
-            } else {
+impl<I: Input> LzssDecoder<I> {
+    fn new(input: I, unpacked_size: Option<u64>) -> Self {
+        LzssDecoder {
+            input,
+            input_consumed: 0,
+            unpacked_size,
+            produced: 0,
+            last_token: None,
+            bits: 0,
+            bits_count: 0,
+            window: Box::new([0; WINDOW_SIZE]),
+            window_pointer: 1,
+            copy_pointer: 0,
+            copy_size: 0,
+        }
+    }
+
+    /// Fills bit buffer from input as much as possible
+    fn refill_bits(&mut self) -> Result<(), DecompressError> {
+        while self.bits_count <= 56 {
+            let input = self.input.fill()?;
+            if input.is_empty() {
                 break;
             }
+            let count = input.len().min(((64 - self.bits_count) / 8) as usize);
+            for &byte in &input[..count] {
+                self.bits |= (byte as u64) << (56 - self.bits_count);
+                self.bits_count += 8;
+            }
+            self.input.consume(count);
+            self.input_consumed += count as u64;
         }
-        Ok(outputted_size)
+        Ok(())
     }
 
-    fn write_to_window(&mut self, value: u8) {
-        self.window[self.window_pointer] = value;
-        self.window_pointer += 1;
-        self.window_pointer %= WINDOW_SIZE;
+    fn take_bits(&mut self, count: u32) -> usize {
+        debug_assert!(count <= self.bits_count);
+        let value = (self.bits >> (64 - count)) as usize;
+        self.bits <<= count;
+        self.bits_count -= count;
+        value
     }
-}
 
-impl<R: Read> Read for CompressedReader<R> {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        if buf.len() == 0 {
-            return Ok(0);
+    /// Copies rest of window reference to `out`, returning number of
+    /// bytes written
+    fn copy_from_window(&mut self, out: &mut [u8]) -> usize {
+        let size = self.copy_size.min(out.len());
+        for out_byte in &mut out[..size] {
+            let value = self.window[self.copy_pointer];
+            self.window[self.window_pointer] = value;
+            *out_byte = value;
+            self.copy_pointer = (self.copy_pointer + 1) & WINDOW_MASK;
+            self.window_pointer = (self.window_pointer + 1) & WINDOW_MASK;
         }
-        if self.output_size > 0 {
-            self.flush_output_buffer(buf)
+        self.copy_size -= size;
+        self.produced += size as u64;
+        size
+    }
+
+    /// Decodes next token, `None` if there's not enough input left
+    fn decode_token(&mut self) -> Result<Option<Token>, DecompressError> {
+        if self.bits_count < MAX_TOKEN_BITS {
+            self.refill_bits()?;
+        }
+        let is_literal = self.bits_count >= 1 && self.bits >> 63 == 1;
+        let token_bits = if is_literal { 9 } else { MAX_TOKEN_BITS };
+        if self.bits_count < token_bits {
+            return Ok(None);
+        }
+
+        self.take_bits(1);
+        Ok(Some(if is_literal {
+            Token::Literal(self.take_bits(8) as u8)
         } else {
-            match self.bit_reader.read_bit()? {
-                true => {
-                    self.bit_reader.read_bytes(&mut buf[0..1])?;
-                    self.write_to_window(buf[0]);
-                    self.bytes_outputted += 1;
-                    Ok(1)
+            let offset = self.take_bits(12);
+            Token::Match {
+                offset,
+                length: self.take_bits(4) + MIN_MATCH,
+            }
+        }))
+    }
+
+    /// Records just decoded `token` as the last one, checking that it
+    /// doesn't extend past unpacked size
+    fn accept_token(&mut self, token: Token) -> Result<(), DecompressError> {
+        self.last_token = Some(token);
+        match self.unpacked_size {
+            Some(unpacked_size) if self.produced + token.length() as u64 > unpacked_size => {
+                let mut context = self.context();
+                context.bit_offset -= token.bits() as u64;
+                Err(DecompressError::MalformedReference { context })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn context(&self) -> StreamContext {
+        StreamContext {
+            bit_offset: self.input_consumed * 8 - self.bits_count as u64,
+            produced: self.produced,
+            unpacked_size: self.unpacked_size,
+            last_token: self.last_token,
+        }
+    }
+
+    /// Decodes into `buf`, returning number of bytes written
+    ///
+    /// End of input before anything is written is
+    /// [DecompressError::PrematureEnd], and so is end of input before
+    /// `unpacked_size` when it's known.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, DecompressError> {
+        let mut written = 0;
+        while written < buf.len() {
+            if self.copy_size > 0 {
+                written += self.copy_from_window(&mut buf[written..]);
+                continue;
+            }
+            if self.unpacked_size == Some(self.produced) {
+                break;
+            }
+
+            let token = match self.decode_token()? {
+                Some(token) => token,
+                None if written > 0 => break,
+                None => return Err(premature_end(self.context())),
+            };
+            self.accept_token(token)?;
+            match token {
+                Token::Literal(value) => {
+                    self.window[self.window_pointer] = value;
+                    self.window_pointer = (self.window_pointer + 1) & WINDOW_MASK;
+                    buf[written] = value;
+                    written += 1;
+                    self.produced += 1;
                 }
-                false => {
-                    let offset: i32 = self.bit_reader.read(12)?;
-                    let size: i32 = self.bit_reader.read(4)?;
-                    self.output_pointer = offset as usize;
-                    self.output_size = (size as usize) + 2;
-                    self.flush_output_buffer(buf)
+                Token::Match { offset, length } => {
+                    self.copy_pointer = offset;
+                    self.copy_size = length;
                 }
             }
         }
+        Ok(written)
+    }
+
+    /// Decodes next token without producing output
+    #[cfg(feature = "std")]
+    fn next_token(&mut self) -> Result<Option<PositionedToken>, DecompressError> {
+        if self.unpacked_size == Some(self.produced) {
+            return Ok(None);
+        }
+        let context = self.context();
+        let token = match self.decode_token()? {
+            Some(token) => token,
+            None if self.unpacked_size.is_none() => return Ok(None),
+            None => return Err(premature_end(context)),
+        };
+        self.accept_token(token)?;
+        self.produced += token.length() as u64;
+        Ok(Some(PositionedToken {
+            bit_offset: context.bit_offset,
+            output_offset: context.produced,
+            token,
+        }))
+    }
+}
+
+fn premature_end(context: StreamContext) -> DecompressError {
+    DecompressError::PrematureEnd {
+        context: Some(context),
     }
 }
 
+/// Wraps decompression error for [Read] implementations, it's
+/// unwrapped back by `From<io::Error>` for [DecompressError]
+#[cfg(feature = "std")]
+fn into_io_error(error: DecompressError) -> io::Error {
+    let kind = match error {
+        DecompressError::FileError { error } => return error,
+        DecompressError::PrematureEnd { .. } => io::ErrorKind::UnexpectedEof,
+        _ => io::ErrorKind::InvalidData,
+    };
+    io::Error::new(kind, error)
+}
+
+/// Decompressing reader
+///
+/// Input is read in blocks, each `read` call decodes as many tokens as
+/// fit into the caller's buffer.
+///
+/// End of compressed stream before `unpacked_size` bytes are produced
+/// is returned as [io::ErrorKind::UnexpectedEof] error wrapping
+/// [DecompressError::PrematureEnd], window reference extending past
+/// `unpacked_size` as [io::ErrorKind::InvalidData] error wrapping
+/// [DecompressError::MalformedReference].
+#[cfg(feature = "std")]
+pub struct CompressedReader<R: Read> {
+    decoder: LzssDecoder<BufReader<R>>,
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> CompressedReader<R> {
+    /// Iterator over tokens of the stream instead of decompressed bytes
+    pub fn tokens(self) -> Tokens<R> {
+        Tokens {
+            reader: self,
+            done: false,
+        }
+    }
+
+    /// Compressed bytes decoded so far, or held for the next token
+    ///
+    /// Unlike bytes read from the underlying reader, this doesn't
+    /// include read-ahead.
+    pub fn consumed(&self) -> u64 {
+        self.decoder.input_consumed
+    }
+
+    /// Returns underlying reader
+    ///
+    /// Input is read ahead in blocks, so bytes after the end of
+    /// compressed stream may already be consumed from it.
+    pub fn into_inner(self) -> R {
+        self.decoder.input.into_inner()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> Read for CompressedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.decoder.read(buf).map_err(into_io_error)
+    }
+}
+
+/// Token with its position in compressed and decompressed streams
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PositionedToken {
+    /// Offset in bits from the start of compressed stream
+    pub bit_offset: u64,
+    /// Offset in decompressed output of the first byte produced
+    pub output_offset: u64,
+    pub token: Token,
+}
+
+impl PositionedToken {
+    /// How far back from `output_offset` referenced bytes are, bytes
+    /// before the start of output are zero-filled part of the window
+    pub fn distance(&self) -> Option<usize> {
+        match self.token {
+            Token::Literal(_) => None,
+            Token::Match { offset, .. } => Some(window_distance(
+                (self.output_offset % WINDOW_SIZE as u64) as usize,
+                offset,
+            )),
+        }
+    }
+}
+
+/// Iterator over tokens of compressed stream, see
+/// [CompressedReader::tokens]
+///
+/// Stops after the last complete token, or with
+/// [DecompressError::PrematureEnd] if unpacked size is known and not
+/// reached. Trailing bits of the last byte are not reported.
+#[cfg(feature = "std")]
+pub struct Tokens<R: Read> {
+    reader: CompressedReader<R>,
+    done: bool,
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> Iterator for Tokens<R> {
+    type Item = io::Result<PositionedToken>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let result = self.reader.decoder.next_token().transpose();
+        self.done = !matches!(result, Some(Ok(_)));
+        result.map(|result| result.map_err(into_io_error))
+    }
+}
+
+/// Decompresses `source` of unknown unpacked size
+///
+/// End of output can't be told apart from truncation, reading past
+/// the last complete token returns [io::ErrorKind::UnexpectedEof].
+#[cfg(feature = "std")]
 pub fn decompress<R>(source: R) -> CompressedReader<R>
 where
     R: Read,
 {
-    let bit_reader = BitReader::new(source);
+    new_reader(source, None)
+}
+
+/// Decompresses `source` into exactly `unpacked_size` bytes
+#[cfg(feature = "std")]
+pub fn decompress_sized<R>(source: R, unpacked_size: u64) -> CompressedReader<R>
+where
+    R: Read,
+{
+    new_reader(source, Some(unpacked_size))
+}
+
+#[cfg(feature = "std")]
+fn new_reader<R: Read>(source: R, unpacked_size: Option<u64>) -> CompressedReader<R> {
+    let input = BufReader::with_capacity(INPUT_BUFFER_SIZE, source);
     CompressedReader {
-        bit_reader,
-        window: [0; WINDOW_SIZE],
-        window_pointer: 1,
-        output_pointer: 0,
-        output_size: 0,
-        bytes_outputted: 0,
+        decoder: LzssDecoder::new(input, unpacked_size),
+    }
+}
+
+/// Decompresses `input` into exactly `unpacked_size` bytes
+///
+/// Errors are the same as of [CompressedReader], without I/O wrapping.
+pub fn decompress_slice(input: &[u8], unpacked_size: usize) -> Result<Vec<u8>, DecompressError> {
+    let mut decoder = LzssDecoder::new(input, Some(unpacked_size as u64));
+    let mut output = vec![0; unpacked_size];
+    let mut size = 0;
+    while size < unpacked_size {
+        size += decoder.read(&mut output[size..])?;
+    }
+    Ok(output)
+}
+
+/// Byte of input at `index`, positions before the start of input
+/// read as zeroes, as in the initial window of decompressor
+fn history_byte(input: &[u8], index: isize) -> u8 {
+    if index < 0 {
+        0
+    } else {
+        input[index as usize]
+    }
+}
+
+fn match_length(input: &[u8], position: usize, distance: usize) -> usize {
+    let max_length = MAX_MATCH.min(input.len() - position);
+    (0..max_length)
+        .take_while(|&i| {
+            input[position + i] == history_byte(input, (position + i) as isize - distance as isize)
+        })
+        .count()
+}
+
+fn pair_hash(input: &[u8], position: usize) -> usize {
+    (input[position] as usize) << 8 | input[position + 1] as usize
+}
+
+fn insert_position(input: &[u8], position: usize, head: &mut [usize], previous: &mut [usize]) {
+    if position + 1 < input.len() {
+        let hash = pair_hash(input, position);
+        previous[position] = head[hash];
+        head[hash] = position;
+    }
+}
+
+/// Finds longest match for `position` as `(distance, length)`
+///
+/// `head` and `previous` are hash chains of already seen positions
+/// keyed by two bytes starting at that position.
+fn longest_match(
+    input: &[u8],
+    position: usize,
+    head: &[usize],
+    previous: &[usize],
+) -> Option<(usize, usize)> {
+    if position + MIN_MATCH > input.len() {
+        return None;
+    }
+    // Window is initially filled with zeroes, so it's referenced as
+    // the furthest distance at the start of input
+    let zero_window = (position < WINDOW_SIZE).then_some(WINDOW_SIZE);
+    let chain = core::iter::successors(Some(head[pair_hash(input, position)]), |&candidate| {
+        (candidate != usize::MAX).then(|| previous[candidate])
+    })
+    .take_while(|&candidate| candidate != usize::MAX && position - candidate <= WINDOW_SIZE)
+    .map(|candidate| position - candidate);
+
+    let mut best: Option<(usize, usize)> = None;
+    for distance in zero_window.into_iter().chain(chain) {
+        let length = match_length(input, position, distance);
+        if length >= MIN_MATCH && best.is_none_or(|(_, best_length)| length > best_length) {
+            best = Some((distance, length));
+            if length == MAX_MATCH {
+                break;
+            }
+        }
+    }
+    best
+}
+
+/// Unit of compressed stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token {
+    Literal(u8),
+    /// Reference to `length` bytes starting at absolute `offset` in
+    /// window
+    Match {
+        offset: usize,
+        length: usize,
+    },
+}
+
+impl Token {
+    /// Number of bytes produced by token
+    pub fn length(&self) -> usize {
+        match self {
+            Token::Literal(_) => 1,
+            Token::Match { length, .. } => *length,
+        }
+    }
+
+    /// Size of encoded token in bits
+    pub fn bits(&self) -> usize {
+        match self {
+            Token::Literal(_) => LITERAL_BITS,
+            Token::Match { .. } => MATCH_BITS,
+        }
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Literal(value) => write!(f, "literal {:#04x}", value),
+            Token::Match { offset, length } => {
+                write!(f, "{} bytes at window offset {:#05x}", length, offset)
+            }
+        }
+    }
+}
+
+/// Window offset referencing bytes `distance` back from `position`
+fn window_offset(position: usize, distance: usize) -> usize {
+    (position + 1 + WINDOW_SIZE - distance) % WINDOW_SIZE
+}
+
+/// Distance back from `position` of bytes at window `offset`, the
+/// position being written to is the furthest one
+fn window_distance(position: usize, offset: usize) -> usize {
+    match (position + 1 + WINDOW_SIZE - offset) % WINDOW_SIZE {
+        0 => WINDOW_SIZE,
+        distance => distance,
+    }
+}
+
+fn greedy_tokens(input: &[u8]) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut head = vec![usize::MAX; 0x10000];
+    let mut previous = vec![usize::MAX; input.len()];
+
+    let mut position = 0;
+    while position < input.len() {
+        let (token, length) = match longest_match(input, position, &head, &previous) {
+            Some((distance, length)) => (
+                Token::Match {
+                    offset: window_offset(position, distance),
+                    length,
+                },
+                length,
+            ),
+            None => (Token::Literal(input[position]), 1),
+        };
+        for p in position..position + length {
+            insert_position(input, p, &mut head, &mut previous);
+        }
+        tokens.push(token);
+        position += length;
+    }
+    tokens
+}
+
+const LITERAL_BITS: usize = 1 + 8;
+const MATCH_BITS: usize = MAX_TOKEN_BITS as usize;
+
+/// Tokens giving the shortest output
+///
+/// Since all matches have the same size in bits, only the longest
+/// match at each position is needed: its prefixes are matches too.
+/// Shortest path is then found from the end of input.
+fn optimal_tokens(input: &[u8]) -> Vec<Token> {
+    let mut head = vec![usize::MAX; 0x10000];
+    let mut previous = vec![usize::MAX; input.len()];
+    let mut matches = Vec::with_capacity(input.len());
+    for position in 0..input.len() {
+        matches.push(longest_match(input, position, &head, &previous));
+        insert_position(input, position, &mut head, &mut previous);
+    }
+
+    // Bits needed to encode input from position, and length of the
+    // first token on that path
+    let mut cost = vec![0; input.len() + 1];
+    let mut step = vec![1; input.len()];
+    for position in (0..input.len()).rev() {
+        cost[position] = LITERAL_BITS + cost[position + 1];
+        if let Some((_, longest)) = matches[position] {
+            for length in MIN_MATCH..=longest {
+                let match_cost = MATCH_BITS + cost[position + length];
+                if match_cost < cost[position] {
+                    cost[position] = match_cost;
+                    step[position] = length;
+                }
+            }
+        }
+    }
+
+    let mut tokens = Vec::new();
+    let mut position = 0;
+    while position < input.len() {
+        let length = step[position];
+        tokens.push(match matches[position] {
+            Some((distance, _)) if length >= MIN_MATCH => Token::Match {
+                offset: window_offset(position, distance),
+                length,
+            },
+            _ => Token::Literal(input[position]),
+        });
+        position += length;
+    }
+    tokens
+}
+
+/// Tokens as the straightforward encoder for this format would choose
+/// them: every window offset is tried in ascending order, including
+/// not yet written zero-filled part, and the first of the longest
+/// matches is taken.
+fn faithful_tokens(input: &[u8]) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut window = [0u8; WINDOW_SIZE];
+    let mut window_pointer = 1;
+
+    let mut position = 0;
+    while position < input.len() {
+        let mut best: Option<(usize, usize)> = None;
+        if position + MIN_MATCH <= input.len() {
+            for (offset, &value) in window.iter().enumerate() {
+                if value != input[position] {
+                    continue;
+                }
+                let length = match_length(input, position, window_distance(position, offset));
+                if length >= MIN_MATCH && best.is_none_or(|(_, best_length)| length > best_length) {
+                    best = Some((offset, length));
+                    if length == MAX_MATCH {
+                        break;
+                    }
+                }
+            }
+        }
+
+        let (token, length) = match best {
+            Some((offset, length)) => (Token::Match { offset, length }, length),
+            None => (Token::Literal(input[position]), 1),
+        };
+        for &value in &input[position..position + length] {
+            window[window_pointer] = value;
+            window_pointer = (window_pointer + 1) & WINDOW_MASK;
+        }
+        tokens.push(token);
+        position += length;
+    }
+    tokens
+}
+
+/// Big-endian bit writer into byte vector
+struct BitWriter {
+    output: Vec<u8>,
+    /// Not yet written bits, aligned to least significant bit
+    bits: u64,
+    bits_count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, count: u32, value: u32) {
+        self.bits = self.bits << count | value as u64;
+        self.bits_count += count;
+        while self.bits_count >= 8 {
+            self.bits_count -= 8;
+            self.output.push((self.bits >> self.bits_count) as u8);
+        }
+    }
+
+    /// Pads the last byte with zero bits
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits_count > 0 {
+            self.output.push((self.bits << (8 - self.bits_count)) as u8);
+        }
+        self.output
+    }
+}
+
+fn write_tokens(tokens: &[Token]) -> Vec<u8> {
+    let mut writer = BitWriter {
+        output: Vec::with_capacity(tokens.len() * 2),
+        bits: 0,
+        bits_count: 0,
+    };
+    for token in tokens {
+        match *token {
+            Token::Literal(value) => {
+                writer.write(1, 1);
+                writer.write(8, value as u32);
+            }
+            Token::Match { offset, length } => {
+                writer.write(1, 0);
+                writer.write(12, offset as u32);
+                writer.write(4, (length - MIN_MATCH) as u32);
+            }
+        }
+    }
+    writer.finish()
+}
+
+/// How compressor chooses matches
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompressionLevel {
+    /// Greedy longest match search using hash chains
+    #[default]
+    Fast,
+    /// Guess at how the original game encoder chooses matches: every
+    /// window offset is tried in ascending order and the first of the
+    /// longest matches is taken. Byte-exact recompression of game
+    /// files is not confirmed yet, check it with
+    /// [crate::verify_recompression]. Much slower than
+    /// [CompressionLevel::Fast].
+    Faithful,
+    /// Optimal parsing, choosing tokens that give the smallest output
+    /// for matches available in window
+    Optimal,
+}
+
+/// Compresses `input` into LZSS bitstream
+///
+/// Output does not include header, see [crate::compress].
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    compress_with_level(input, CompressionLevel::default())
+}
+
+pub fn compress_with_level(input: &[u8], level: CompressionLevel) -> Vec<u8> {
+    let tokens = match level {
+        CompressionLevel::Fast => greedy_tokens(input),
+        CompressionLevel::Faithful => faithful_tokens(input),
+        CompressionLevel::Optimal => optimal_tokens(input),
+    };
+    write_tokens(&tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(input: &[u8]) {
+        let compressed = compress(input);
+        assert_eq!(
+            input,
+            &decompress_slice(&compressed, input.len()).unwrap()[..]
+        );
+        #[cfg(feature = "std")]
+        {
+            let mut output = Vec::new();
+            decompress(&compressed[..])
+                .take(input.len() as u64)
+                .read_to_end(&mut output)
+                .unwrap();
+            assert_eq!(input, &output[..]);
+        }
+    }
+
+    #[test]
+    fn test_compress_then_decompress() {
+        roundtrip(b"The quick brown fox jumps over the lazy dog");
+        roundtrip(&b"abcabcabcabcabcabcabcabcabcabcabcabcabcabcabcabc"[..]);
+        roundtrip(&[0; 100]);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_decompress_in_small_reads() {
+        let input: Vec<u8> = (0..20000u32).map(|i| (i * 7 / 5 % 251) as u8).collect();
+        let compressed = compress(&input);
+        let mut reader = decompress(&compressed[..]);
+        let mut output = Vec::new();
+        let mut chunk = [0; 5];
+        while output.len() < input.len() {
+            let size = reader.read(&mut chunk).unwrap();
+            assert!(size > 0);
+            output.extend_from_slice(&chunk[..size]);
+        }
+        assert_eq!(input, output);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_decompress_truncated() {
+        let input: Vec<u8> = (0..1000u32).map(|i| (i * 7 / 5 % 251) as u8).collect();
+        let compressed = compress(&input);
+        let mut output = Vec::new();
+        let result = decompress(&compressed[..compressed.len() / 2])
+            .take(input.len() as u64)
+            .read_to_end(&mut output);
+        assert_eq!(
+            std::io::ErrorKind::UnexpectedEof,
+            result.unwrap_err().kind()
+        );
+    }
+
+    #[cfg(feature = "std")]
+    fn stream_error(result: io::Result<usize>) -> (io::ErrorKind, DecompressError) {
+        let error = result.unwrap_err();
+        let kind = error.kind();
+        (kind, *error.into_inner().unwrap().downcast().unwrap())
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_decompress_sized_truncated() {
+        // Literals 'a' and 'b', then a reference to both
+        let compressed = write_tokens(&[
+            Token::Literal(b'a'),
+            Token::Literal(b'b'),
+            Token::Match {
+                offset: 1,
+                length: 2,
+            },
+        ]);
+        let mut output = Vec::new();
+        decompress_sized(&compressed[..], 4)
+            .read_to_end(&mut output)
+            .unwrap();
+        assert_eq!(b"abab", &output[..]);
+
+        // Cut inside the reference
+        let mut output = Vec::new();
+        let result = decompress_sized(&compressed[..4], 4).read_to_end(&mut output);
+        assert_eq!(b"ab", &output[..]);
+        match stream_error(result) {
+            (io::ErrorKind::UnexpectedEof, DecompressError::PrematureEnd { context }) => {
+                assert_eq!(
+                    Some(StreamContext {
+                        bit_offset: 18,
+                        produced: 2,
+                        unpacked_size: Some(4),
+                        last_token: Some(Token::Literal(b'b')),
+                    }),
+                    context
+                )
+            }
+            other => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_decompress_slice() {
+        let input: Vec<u8> = (0..20000u32).map(|i| (i * 7 / 5 % 251) as u8).collect();
+        let compressed = compress(&input);
+        assert_eq!(input, decompress_slice(&compressed, input.len()).unwrap());
+
+        match decompress_slice(&compressed[..100], input.len()).unwrap_err() {
+            DecompressError::PrematureEnd {
+                context: Some(context),
+            } => assert!(context.produced > 0 && context.produced < input.len() as u64),
+            other => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_decompress_sized_reference_past_end() {
+        let compressed = write_tokens(&[
+            Token::Literal(b'a'),
+            Token::Match {
+                offset: 1,
+                length: 5,
+            },
+        ]);
+        let mut output = Vec::new();
+        let result = decompress_sized(&compressed[..], 4).read_to_end(&mut output);
+        match stream_error(result) {
+            (io::ErrorKind::InvalidData, DecompressError::MalformedReference { context }) => {
+                assert_eq!(
+                    StreamContext {
+                        bit_offset: 9,
+                        produced: 1,
+                        unpacked_size: Some(4),
+                        last_token: Some(Token::Match {
+                            offset: 1,
+                            length: 5
+                        }),
+                    },
+                    context
+                )
+            }
+            other => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_tokens() {
+        let compressed = write_tokens(&[
+            Token::Literal(b'a'),
+            Token::Literal(b'b'),
+            Token::Match {
+                offset: 1,
+                length: 5,
+            },
+            Token::Literal(b'c'),
+        ]);
+        let tokens: Vec<PositionedToken> = decompress_sized(&compressed[..], 8)
+            .tokens()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(
+            vec![
+                PositionedToken {
+                    bit_offset: 0,
+                    output_offset: 0,
+                    token: Token::Literal(b'a'),
+                },
+                PositionedToken {
+                    bit_offset: 9,
+                    output_offset: 1,
+                    token: Token::Literal(b'b'),
+                },
+                PositionedToken {
+                    bit_offset: 18,
+                    output_offset: 2,
+                    token: Token::Match {
+                        offset: 1,
+                        length: 5,
+                    },
+                },
+                PositionedToken {
+                    bit_offset: 35,
+                    output_offset: 7,
+                    token: Token::Literal(b'c'),
+                },
+            ],
+            tokens
+        );
+        assert_eq!(Some(2), tokens[2].distance());
+
+        // Without known size, trailing bits are too few for a token
+        let unsized_tokens = decompress(&compressed[..]).tokens();
+        assert_eq!(4, unsized_tokens.map(Result::unwrap).count());
+
+        let mut truncated = decompress_sized(&compressed[..4], 8).tokens();
+        assert_eq!(2, truncated.by_ref().take_while(Result::is_ok).count());
+        assert!(truncated.next().is_none());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_tokens_match_decompressed_output() {
+        let input: Vec<u8> = (0..20000u32).map(|i| (i * 7 / 5 % 251) as u8).collect();
+        let compressed = compress(&input);
+        let mut output_offset = 0;
+        for token in decompress_sized(&compressed[..], input.len() as u64).tokens() {
+            let token = token.unwrap();
+            assert_eq!(output_offset, token.output_offset);
+            match token.token {
+                Token::Literal(value) => assert_eq!(input[output_offset as usize], value),
+                Token::Match { length, .. } => {
+                    let start = output_offset as usize;
+                    let distance = token.distance().unwrap();
+                    for i in start..start + length {
+                        assert_eq!(
+                            input[i],
+                            history_byte(&input, i as isize - distance as isize)
+                        );
+                    }
+                }
+            }
+            output_offset += token.token.length() as u64;
+        }
+        assert_eq!(input.len() as u64, output_offset);
+    }
+
+    #[test]
+    fn test_compress_then_decompress_past_window() {
+        let input: Vec<u8> = (0..20000u32).map(|i| (i * 7 / 5 % 251) as u8).collect();
+        roundtrip(&input);
+    }
+
+    fn roundtrip_level(input: &[u8], level: CompressionLevel) {
+        let compressed = compress_with_level(input, level);
+        let output = decompress_slice(&compressed, input.len()).unwrap();
+        assert_eq!(input, &output[..], "{:?}", level);
+    }
+
+    const LEVELS: [CompressionLevel; 3] = [
+        CompressionLevel::Fast,
+        CompressionLevel::Faithful,
+        CompressionLevel::Optimal,
+    ];
+
+    #[test]
+    fn test_compress_all_levels() {
+        let text: Vec<u8> = b"The quick brown fox jumps over the lazy dog. "
+            .iter()
+            .cycle()
+            .take(3000)
+            .copied()
+            .collect();
+        let structured: Vec<u8> = (0..20000u32).map(|i| (i * 7 / 5 % 251) as u8).collect();
+        for level in LEVELS {
+            roundtrip_level(b"", level);
+            roundtrip_level(b"a", level);
+            roundtrip_level(&text, level);
+            roundtrip_level(&structured, level);
+            roundtrip_level(&[0; 5000], level);
+        }
+    }
+
+    #[test]
+    fn test_optimal_not_larger() {
+        let inputs: [Vec<u8>; 3] = [
+            (0..20000u32).map(|i| (i * 7 / 5 % 251) as u8).collect(),
+            (0..20000u32).map(|i| (i * i / 13 % 7) as u8).collect(),
+            b"abcbcdcdedefabcdef".repeat(50),
+        ];
+        for input in inputs {
+            let optimal = compress_with_level(&input, CompressionLevel::Optimal).len();
+            assert!(optimal <= compress_with_level(&input, CompressionLevel::Fast).len());
+            assert!(optimal <= compress_with_level(&input, CompressionLevel::Faithful).len());
+        }
+    }
+
+    fn bits(tokens: &[Token]) -> usize {
+        tokens
+            .iter()
+            .map(|token| match token {
+                Token::Literal(_) => LITERAL_BITS,
+                Token::Match { .. } => MATCH_BITS,
+            })
+            .sum()
+    }
+
+    #[test]
+    fn test_optimal_parse() {
+        // Greedy takes "ab" and then "c..r", optimal takes "a" as
+        // literal and then "b..r" as a single match
+        let input = b"xab-bcdefghijklmnopqr-abcdefghijklmnopqr";
+        let optimal = optimal_tokens(input);
+        let greedy = greedy_tokens(input);
+        assert!(bits(&optimal) < bits(&greedy));
+        assert_eq!(
+            Some(&Token::Literal(b'a')),
+            optimal[optimal.len() - 2..].first()
+        );
+    }
+
+    #[test]
+    fn test_faithful_prefers_lowest_offset() {
+        // Zero-filled window at offset 0 is the first candidate
+        assert_eq!(
+            vec![Token::Match {
+                offset: 0,
+                length: 2
+            }],
+            faithful_tokens(&[0, 0])
+        );
+        assert_eq!(
+            vec![
+                Token::Literal(1),
+                Token::Literal(2),
+                Token::Match {
+                    offset: 1,
+                    length: 2
+                },
+            ],
+            faithful_tokens(&[1, 2, 1, 2])
+        );
+    }
+
+    #[test]
+    fn test_compress_repeated_is_smaller() {
+        assert!(compress(&[7; 1000]).len() < 200);
     }
 }
@@ -0,0 +1,276 @@
+//! Streaming deobfuscation and decompression
+
+use std::io::{self, Read, Take};
+
+use crate::compression::{self, CompressedReader};
+use crate::obfuscation::DeobfuscatingReader;
+use crate::{
+    ChecksumCheck, ChecksumMode, ChecksumReport, ChecksummingReader, CompressionType,
+    DecompressError, DecompressOptions, Header, HEADER_SIZE,
+};
+
+type Source<R> = ChecksummingReader<DeobfuscatingReader<R>>;
+
+enum Body<R: Read> {
+    Uncompressed(R),
+    Lzss(Box<CompressedReader<R>>),
+}
+
+impl<R: Read> Body<R> {
+    fn into_inner(self) -> R {
+        match self {
+            Body::Uncompressed(reader) => reader,
+            Body::Lzss(reader) => reader.into_inner(),
+        }
+    }
+}
+
+impl<R: Read> Read for Body<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Body::Uncompressed(reader) => reader.read(buf),
+            Body::Lzss(reader) => reader.read(buf),
+        }
+    }
+}
+
+/// Reader that deobfuscates and decompresses file as it's read
+///
+/// Checksums are verified when end of output is reached, mismatch
+/// is returned as [io::ErrorKind::InvalidData] error wrapping
+/// [DecompressError], unless [ChecksumMode::Lenient] is used.
+///
+/// ```no_run
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let file = std::fs::File::open("CFsec50.map")?;
+/// let mut decoder = mm_compression::Decoder::new(std::io::BufReader::new(file))?;
+/// std::io::copy(&mut decoder, &mut std::io::stdout())?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Decoder<R: Read> {
+    header: Header,
+    options: DecompressOptions,
+    output: Option<ChecksummingReader<Take<Body<Source<R>>>>>,
+    checksums: Option<ChecksumReport>,
+    size: u64,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Reads and checks header of obfuscated file from `reader`
+    pub fn new(reader: R) -> Result<Self, DecompressError> {
+        Self::with_options(reader, &DecompressOptions::default())
+    }
+
+    pub fn with_options(reader: R, options: &DecompressOptions) -> Result<Self, DecompressError> {
+        let mut deobfuscating_reader =
+            DeobfuscatingReader::new(reader).map_err(DecompressError::from_header_read)?;
+        let mut header_bytes = [0; HEADER_SIZE];
+        deobfuscating_reader
+            .read_exact(&mut header_bytes)
+            .map_err(DecompressError::from_header_read)?;
+        let header = Header::from_bytes(&header_bytes)?;
+        options.check_unpacked_size(&header)?;
+
+        let source = ChecksummingReader::new(deobfuscating_reader);
+
+        let (body, limit) = match header.compression {
+            CompressionType::Uncompressed => (Body::Uncompressed(source), u64::MAX),
+            CompressionType::LZSS => (
+                Body::Lzss(Box::new(compression::decompress_sized(
+                    source,
+                    header.unpacked_size as u64,
+                ))),
+                header.unpacked_size as u64,
+            ),
+            CompressionType::RLE | CompressionType::Unknown => {
+                return Err(DecompressError::CompressionNotSupported)
+            }
+        };
+
+        Ok(Decoder {
+            header,
+            options: options.clone(),
+            output: Some(ChecksummingReader::new(body.take(limit))),
+            checksums: None,
+            size: 0,
+        })
+    }
+
+    /// Checksums of the file, available when end of output is reached
+    pub fn checksums(&self) -> Option<ChecksumReport> {
+        self.checksums
+    }
+
+    fn finish(&mut self) -> Result<(), DecompressError> {
+        let output = match self.output.take() {
+            Some(output) => output,
+            None => return Ok(()),
+        };
+        let checksum_uncompressed = output.checksum();
+        let mut source = output.into_inner().into_inner().into_inner();
+        // Rest of input is still covered by deobfuscation checksum
+        io::copy(&mut source, &mut io::sink())?;
+
+        let checksums = ChecksumReport {
+            deobfuscation: ChecksumCheck {
+                expected: self.header.checksum_deobfuscated,
+                computed: source.checksum(),
+            },
+            decompression: match self.header.compression {
+                CompressionType::Uncompressed => None,
+                _ => Some(ChecksumCheck {
+                    expected: self.header.checksum_uncompressed,
+                    computed: checksum_uncompressed,
+                }),
+            },
+        };
+        self.checksums = Some(checksums);
+        match self.options.checksums {
+            ChecksumMode::Strict => checksums.verify(),
+            ChecksumMode::Lenient => Ok(()),
+        }
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let output = match self.output.as_mut() {
+            Some(output) => output,
+            None => return Ok(0),
+        };
+        let size = output.read(buf)?;
+        self.size += size as u64;
+        if let Body::Lzss(body) = output.get_ref().get_ref() {
+            self.options
+                .check_ratio(self.size, body.consumed())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        }
+        if size == 0 && !buf.is_empty() {
+            self.finish()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        }
+        Ok(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::compressed_sample;
+    use crate::{compress, decompress, pack, CompressOptions};
+
+    fn decode(input: &[u8]) -> io::Result<Vec<u8>> {
+        let mut output = Vec::new();
+        Decoder::new(input)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+            .read_to_end(&mut output)?;
+        Ok(output)
+    }
+
+    #[test]
+    fn test_decoder() {
+        let (source, mut compressed) = compressed_sample(777);
+        assert_eq!(source, decode(&compressed).unwrap());
+        assert_eq!(source, decompress(&mut compressed).unwrap());
+    }
+
+    #[test]
+    fn test_decoder_corrupted() {
+        let (_, mut compressed) = compressed_sample(777);
+        compressed[100] ^= 0x01;
+        let error = decode(&compressed).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+        assert!(matches!(
+            error.into_inner().unwrap().downcast_ref(),
+            Some(DecompressError::DeobfuscateChecksumNotMatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_decoder_lenient() {
+        let (source, mut compressed) = compressed_sample(777);
+        compressed[100] ^= 0x01;
+        let options = DecompressOptions {
+            checksums: ChecksumMode::Lenient,
+            ..Default::default()
+        };
+        let mut decoder = Decoder::with_options(&compressed[..], &options).unwrap();
+        assert_eq!(None, decoder.checksums());
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output).unwrap();
+        assert_eq!(source.len(), output.len());
+        assert!(!decoder.checksums().unwrap().deobfuscation.matches());
+    }
+
+    #[test]
+    fn test_decoder_limits() {
+        let source = vec![0; 100000];
+        let compressed = compress(
+            &source,
+            &CompressOptions {
+                seed: 1,
+                ..Default::default()
+            },
+        );
+        let options = DecompressOptions {
+            max_output_size: Some(1000),
+            ..Default::default()
+        };
+        assert!(matches!(
+            Decoder::with_options(&compressed[..], &options),
+            Err(DecompressError::OutputTooLarge { .. })
+        ));
+
+        let options = DecompressOptions {
+            max_ratio: Some(4),
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        let error = Decoder::with_options(&compressed[..], &options)
+            .unwrap()
+            .read_to_end(&mut output)
+            .unwrap_err();
+        assert!(matches!(
+            error.into_inner().unwrap().downcast_ref(),
+            Some(DecompressError::RatioTooLarge { limit: 4, .. })
+        ));
+    }
+
+    #[test]
+    fn test_decoder_ratio_ignores_read_ahead() {
+        let source = vec![0; 100000];
+        let mut body = compression::compress(&source);
+        let body_size = body.len() as u64;
+        // Zero padding after the stream doesn't change the checksum
+        body.resize(body.len() + 0x10000, 0);
+        let packed = pack(&source, &body, CompressionType::LZSS, 1);
+
+        // Passes if up to a block of read-ahead is counted
+        let limit = (source.len() as u64 / (body_size + 0x1000) + 1) as u32;
+        assert!(source.len() as u64 > limit as u64 * body_size);
+        let options = DecompressOptions {
+            max_ratio: Some(limit),
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        let error = Decoder::with_options(&packed[..], &options)
+            .unwrap()
+            .read_to_end(&mut output)
+            .unwrap_err();
+        match error.into_inner().unwrap().downcast_ref() {
+            Some(DecompressError::RatioTooLarge {
+                compressed_size, ..
+            }) => assert!(*compressed_size <= body_size),
+            other => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_decoder_too_small() {
+        assert!(matches!(
+            Decoder::new(&[1, 2, 3, 4, 5, 6][..]),
+            Err(DecompressError::ContentTooSmall)
+        ));
+    }
+}
@@ -0,0 +1,278 @@
+//! Detection of obfuscated and plain game files
+
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+use crate::{
+    decompress_with_options, deobfuscate, inspect_slice, unpack, ChecksumCheck, ChecksumMode,
+    ChecksumReport, CompressionType, DecompressError, DecompressOptions, Header,
+    DEFAULT_MAX_OUTPUT_SIZE,
+};
+
+/// Magic value at the start of sprite sheets, such as Terrain.spr
+pub const SPRITES_MAGIC: &[u8] = b"SPR\0";
+
+/// Magic values of files that are stored as is
+const PLAIN_MAGIC: &[&[u8]] = &[SPRITES_MAGIC];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    /// Not obfuscated file starting with known magic value
+    Plain,
+    /// Obfuscated file with valid header and checksum
+    Obfuscated(CompressionType),
+    /// Neither known magic value nor valid obfuscation
+    Unknown,
+}
+
+#[derive(Debug)]
+pub struct GameFile {
+    pub kind: FileKind,
+    /// Decoded contents, or contents as is for not obfuscated files
+    pub contents: Vec<u8>,
+}
+
+/// Detects whether `contents` is obfuscated and decodes it if so
+///
+/// Obfuscated files that fail to decompress are reported as errors,
+/// files which are not recognized are returned as is with
+/// [FileKind::Unknown]. File with mismatching deobfuscation checksum
+/// is considered obfuscated, but corrupt, if its header is plausible,
+/// see [check_integrity].
+pub fn decode_game_file(contents: Vec<u8>) -> Result<GameFile, DecompressError> {
+    if is_plain(&contents) {
+        return Ok(GameFile {
+            kind: FileKind::Plain,
+            contents,
+        });
+    }
+
+    match deobfuscate(&contents) {
+        Ok((deobfuscated, deobfuscation)) => {
+            let header = Header::from_bytes(&deobfuscated)?;
+            if !deobfuscation.matches() {
+                if !is_plausible(header.compression, header.unpacked_size) {
+                    return Ok(GameFile {
+                        kind: FileKind::Unknown,
+                        contents,
+                    });
+                }
+                let ChecksumCheck { expected, computed } = deobfuscation;
+                return Err(DecompressError::DeobfuscateChecksumNotMatch { expected, computed });
+            }
+            let (data, decompression) = unpack(&deobfuscated, &DecompressOptions::default())?;
+            ChecksumReport {
+                deobfuscation,
+                decompression,
+            }
+            .verify()?;
+            Ok(GameFile {
+                kind: FileKind::Obfuscated(header.compression),
+                contents: data,
+            })
+        }
+        Err(DecompressError::ObfuscateFileTooSmall) | Err(DecompressError::PrematureEnd { .. }) => {
+            Ok(GameFile {
+                kind: FileKind::Unknown,
+                contents,
+            })
+        }
+        Err(e) => Err(e),
+    }
+}
+
+fn is_plain(contents: &[u8]) -> bool {
+    PLAIN_MAGIC.iter().any(|magic| contents.starts_with(magic))
+}
+
+/// Whether deobfuscated header looks like one of obfuscated file:
+/// known compression type and unpacked size within default limit
+fn is_plausible(compression: CompressionType, unpacked_size: u32) -> bool {
+    compression != CompressionType::Unknown && unpacked_size as u64 <= DEFAULT_MAX_OUTPUT_SIZE
+}
+
+/// Result of [check_integrity]
+#[derive(Debug)]
+pub enum Integrity {
+    /// Plain file or not a game file at all
+    NotObfuscated,
+    /// Obfuscated file decompressing with matching checksums
+    Valid(CompressionType),
+    /// Decompressed, but stored and computed checksums differ
+    ChecksumMismatch(CompressionType, ChecksumReport),
+    /// Compressed stream ends before unpacked size is reached
+    Truncated(CompressionType, DecompressError),
+    /// Failed to decompress for other reason
+    Corrupt(CompressionType, DecompressError),
+    /// Obfuscated file with valid checksum, but unsupported
+    /// compression type
+    Unsupported(CompressionType),
+}
+
+impl Integrity {
+    /// Whether file is obfuscated and has problems
+    pub fn is_problem(&self) -> bool {
+        !matches!(self, Integrity::NotObfuscated | Integrity::Valid(_))
+    }
+}
+
+/// Checks that obfuscated file decompresses and its checksums match
+///
+/// File with mismatching deobfuscation checksum is considered
+/// obfuscated, but corrupt, if its header is plausible: known
+/// compression type and unpacked size within default limit.
+/// Otherwise it's [Integrity::NotObfuscated].
+pub fn check_integrity(contents: &[u8]) -> Integrity {
+    if is_plain(contents) {
+        return Integrity::NotObfuscated;
+    }
+    let info = match inspect_slice(contents) {
+        Ok(info) => info,
+        Err(_) => return Integrity::NotObfuscated,
+    };
+    let compression = info.compression;
+    match compression {
+        CompressionType::RLE | CompressionType::Unknown if info.deobfuscation_checksum_matches => {
+            return Integrity::Unsupported(compression)
+        }
+        _ if !info.deobfuscation_checksum_matches
+            && !is_plausible(compression, info.unpacked_size) =>
+        {
+            return Integrity::NotObfuscated
+        }
+        _ => {}
+    }
+
+    // Unpacked size is limited already, without ratio limit truncated
+    // files are reported as such
+    let options = DecompressOptions {
+        checksums: ChecksumMode::Lenient,
+        max_ratio: None,
+        ..Default::default()
+    };
+    match decompress_with_options(contents, &options) {
+        Ok(result) if result.checksums.matches() => Integrity::Valid(compression),
+        Ok(result) => Integrity::ChecksumMismatch(compression, result.checksums),
+        Err(error @ DecompressError::PrematureEnd { .. }) => {
+            Integrity::Truncated(compression, error)
+        }
+        Err(error) => Integrity::Corrupt(compression, error),
+    }
+}
+
+/// Reads file from `path`, deobfuscating and decompressing it if needed
+#[cfg(feature = "std")]
+pub fn open_game_file<P: AsRef<Path>>(path: P) -> Result<GameFile, DecompressError> {
+    decode_game_file(fs::read(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compress, CompressOptions};
+    use alloc::vec;
+
+    #[test]
+    fn test_decode_plain() {
+        let contents = b"SPR\0\x01\x02\x03\x04".to_vec();
+        let file = decode_game_file(contents.clone()).unwrap();
+        assert_eq!(FileKind::Plain, file.kind);
+        assert_eq!(contents, file.contents);
+    }
+
+    #[test]
+    fn test_decode_obfuscated() {
+        let source: Vec<u8> = (0..500u32).map(|i| (i % 13) as u8).collect();
+        let file = decode_game_file(compress(
+            &source,
+            &CompressOptions {
+                seed: 5,
+                ..Default::default()
+            },
+        ))
+        .unwrap();
+        assert_eq!(FileKind::Obfuscated(CompressionType::LZSS), file.kind);
+        assert_eq!(source, file.contents);
+    }
+
+    #[test]
+    fn test_decode_corrupted() {
+        let source: Vec<u8> = (0..500u32).map(|i| (i % 13) as u8).collect();
+        let mut compressed = compress(&source, &CompressOptions::default());
+        compressed[50] ^= 0x01;
+        assert!(matches!(
+            decode_game_file(compressed).unwrap_err(),
+            DecompressError::DeobfuscateChecksumNotMatch { .. }
+        ));
+    }
+
+    #[test]
+    fn test_check_integrity() {
+        let source: Vec<u8> = (0..3000u32).map(|i| (i * i / 7 % 17) as u8).collect();
+        let compressed = compress(
+            &source,
+            &CompressOptions {
+                seed: 5,
+                ..Default::default()
+            },
+        );
+        assert!(matches!(
+            check_integrity(&compressed),
+            Integrity::Valid(CompressionType::LZSS)
+        ));
+
+        let mut corrupted = compressed.clone();
+        corrupted[100] ^= 0x01;
+        let integrity = check_integrity(&corrupted);
+        assert!(integrity.is_problem());
+        assert!(matches!(
+            integrity,
+            Integrity::ChecksumMismatch(CompressionType::LZSS, _)
+                | Integrity::Corrupt(CompressionType::LZSS, _)
+        ));
+
+        for size in [compressed.len() / 2, 30] {
+            assert!(matches!(
+                check_integrity(&compressed[..size]),
+                Integrity::Truncated(CompressionType::LZSS, _)
+            ));
+        }
+
+        for compression in [CompressionType::RLE, CompressionType::Unknown] {
+            let unsupported = crate::pack(&source, &source, compression, 5);
+            assert!(matches!(
+                check_integrity(&unsupported),
+                Integrity::Unsupported(c) if c == compression
+            ));
+        }
+
+        for contents in [
+            &b"SPR\0\x01\x02"[..],
+            &[1, 2],
+            &[7; 100],
+            b"plain text file",
+        ] {
+            assert!(matches!(
+                check_integrity(contents),
+                Integrity::NotObfuscated
+            ));
+        }
+    }
+
+    #[test]
+    fn test_decode_unknown() {
+        for contents in [
+            vec![],
+            vec![1, 2],
+            vec![0; 10],
+            (0..100).collect::<Vec<u8>>(),
+        ] {
+            let file = decode_game_file(contents.clone()).unwrap();
+            assert_eq!(FileKind::Unknown, file.kind);
+            assert_eq!(contents, file.contents);
+        }
+    }
+}
@@ -6,26 +6,78 @@
 //! i.e. there's no just compressed but not obfuscated files and vice
 //! versa). Files are, obviously, first compressed and then
 //! obfuscated.
+//!
+//! Readers, writers and file functions require `std` feature, enabled
+//! by default. Without it the crate is `no_std` and works on byte
+//! slices, using `alloc` for output.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
 
-mod compression;
-mod obfuscation;
+pub mod checksum;
+pub mod compression;
+#[cfg(feature = "std")]
+mod decoder;
+mod game_file;
+pub mod obfuscation;
+mod recompression;
+#[cfg(any(test, feature = "std"))]
 pub mod test_utils;
 
-use std::convert::TryInto;
-use std::error;
-use std::fmt;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use core::error;
+use core::fmt;
+#[cfg(feature = "std")]
 use std::fs::File;
+#[cfg(feature = "std")]
 use std::io::prelude::*;
+#[cfg(feature = "std")]
 use std::path::Path;
 
-#[derive(Debug)]
-enum CompressionType {
+pub use checksum::Checksum;
+#[cfg(feature = "std")]
+pub use checksum::{ChecksummingReader, ChecksummingWriter};
+pub use compression::CompressionLevel;
+#[cfg(feature = "std")]
+pub use decoder::Decoder;
+#[cfg(feature = "std")]
+pub use game_file::open_game_file;
+pub use game_file::{
+    check_integrity, decode_game_file, FileKind, GameFile, Integrity, SPRITES_MAGIC,
+};
+#[cfg(feature = "std")]
+pub use obfuscation::{DeobfuscatingReader, ObfuscatingWriter};
+pub use recompression::{verify_recompression, Divergence};
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionType {
     Uncompressed = 0,
     RLE = 1,
     LZSS = 2,
     Unknown,
 }
 
+/// Compression types [compress] can write, unlike [CompressionType]
+/// read from headers
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Uncompressed,
+    LZSS,
+}
+
+impl From<Compression> for CompressionType {
+    fn from(compression: Compression) -> Self {
+        match compression {
+            Compression::Uncompressed => CompressionType::Uncompressed,
+            Compression::LZSS => CompressionType::LZSS,
+        }
+    }
+}
+
 #[derive(Debug)]
 struct Header {
     unpacked_size: u32,
@@ -34,10 +86,14 @@ struct Header {
     compression: CompressionType,
 }
 
-const HEADER_SIZE: usize = 4 * 4;
+/// Size of header following the seed in obfuscated files
+pub const HEADER_SIZE: usize = 4 * 4;
 
 impl Header {
     pub fn from_bytes(input: &[u8]) -> Result<Header, DecompressError> {
+        let input = input
+            .get(..HEADER_SIZE)
+            .ok_or(DecompressError::PrematureEnd { context: None })?;
         Ok(Header {
             unpacked_size: u32::from_le_bytes(input[0..0x4].try_into()?),
             checksum_deobfuscated: u32::from_le_bytes(input[0x4..0x8].try_into()?),
@@ -50,52 +106,125 @@ impl Header {
             },
         })
     }
+
+    fn to_bytes(&self) -> [u8; HEADER_SIZE] {
+        let mut result = [0; HEADER_SIZE];
+        result[0..0x4].copy_from_slice(&self.unpacked_size.to_le_bytes());
+        result[0x4..0x8].copy_from_slice(&self.checksum_deobfuscated.to_le_bytes());
+        result[0x8..0xc].copy_from_slice(&self.checksum_uncompressed.to_le_bytes());
+        result[0xc..0x10].copy_from_slice(&(self.compression as u32).to_le_bytes());
+        result
+    }
 }
 
+/// Variants depend on enabled features, `FileError` only exists with `std`
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum DecompressError {
-    DeobfuscateChecksumNotMatch,
+    DeobfuscateChecksumNotMatch {
+        expected: u32,
+        computed: u32,
+    },
     ObfuscateFileTooSmall,
-    DecompressChecksumNonMatch,
+    DecompressChecksumNonMatch {
+        expected: u32,
+        computed: u32,
+    },
     InvalidCompressionType,
     CompressionNotSupported,
     ContentTooSmall,
-    FileError { error: std::io::Error },
-    PrematureEnd { context: Option<u32> },
+    #[cfg(feature = "std")]
+    FileError {
+        error: std::io::Error,
+    },
+    /// Input ended early, with position in compressed stream if it
+    /// ended there
+    PrematureEnd {
+        context: Option<compression::StreamContext>,
+    },
+    /// Window reference extends past unpacked size
+    MalformedReference {
+        context: compression::StreamContext,
+    },
+    OutputTooLarge {
+        size: u64,
+        limit: u64,
+    },
+    RatioTooLarge {
+        size: u64,
+        compressed_size: u64,
+        limit: u32,
+    },
 }
 
 impl fmt::Display for DecompressError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            DecompressError::DeobfuscateChecksumNotMatch => {
-                write!(f, "deobfuscation checksum does not match")
-            }
-            DecompressError::DecompressChecksumNonMatch => {
-                write!(f, "decompression checksum does not match")
-            }
+            DecompressError::DeobfuscateChecksumNotMatch { expected, computed } => write!(
+                f,
+                "deobfuscation checksum does not match: expected {:#010x}, computed {:#010x}",
+                expected, computed
+            ),
+            DecompressError::DecompressChecksumNonMatch { expected, computed } => write!(
+                f,
+                "decompression checksum does not match: expected {:#010x}, computed {:#010x}",
+                expected, computed
+            ),
             DecompressError::ObfuscateFileTooSmall => {
                 write!(f, "file too small for obfuscation/deobfuscation")
             }
             DecompressError::InvalidCompressionType => write!(f, "invalid compression type"),
             DecompressError::CompressionNotSupported => write!(f, "compression not supported"),
             DecompressError::ContentTooSmall => write!(f, "file contents are too small"),
+            #[cfg(feature = "std")]
             DecompressError::FileError { error: e } => write!(f, "file reading error: {}", e),
             DecompressError::PrematureEnd { context: None } => write!(f, "premature end of file"),
-            DecompressError::PrematureEnd {
-                context: Some(line),
+            DecompressError::OutputTooLarge { size, limit } => {
+                write!(f, "unpacked size {} exceeds limit of {} bytes", size, limit)
+            }
+            DecompressError::RatioTooLarge {
+                size,
+                compressed_size,
+                limit,
             } => write!(
                 f,
-                "premature end of file when lz unpacking, lz.unpack.rs line {}",
-                line
+                "unpacked size {} from {} compressed bytes exceeds ratio limit of {}",
+                size, compressed_size, limit
             ),
+            DecompressError::PrematureEnd {
+                context: Some(context),
+            } => write!(f, "compressed stream ends early {}", context),
+            DecompressError::MalformedReference { context } => {
+                write!(f, "window reference past end of output {}", context)
+            }
         }
     }
 }
 
 impl error::Error for DecompressError {}
 
+#[cfg(feature = "std")]
+impl DecompressError {
+    /// Error when reading seed or header, where end of file means
+    /// that file is too small
+    fn from_header_read(error: std::io::Error) -> Self {
+        match error.kind() {
+            std::io::ErrorKind::UnexpectedEof => DecompressError::ContentTooSmall,
+            _ => DecompressError::FileError { error },
+        }
+    }
+}
+
+#[cfg(feature = "std")]
 impl From<std::io::Error> for DecompressError {
+    /// Unwraps [DecompressError] returned from readers as I/O error
     fn from(error: std::io::Error) -> Self {
+        if error
+            .get_ref()
+            .is_some_and(|inner| inner.is::<DecompressError>())
+        {
+            return *error.into_inner().unwrap().downcast().unwrap();
+        }
         DecompressError::FileError { error }
     }
 }
@@ -112,109 +241,331 @@ impl From<obfuscation::InputTooSmall> for DecompressError {
     }
 }
 
-struct ChecksummingReader<R: Read> {
-    reader: R,
-    pub checksum: u32,
-    odd: bool,
-    current_word: u32,
-    current_word_fill: u32,
-}
-
-impl<R: Read> Read for ChecksummingReader<R> {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        let result = self.reader.read(buf);
-        if let Ok(bytes_read) = result {
-            for &byte in &buf[0..bytes_read] {
-                self.current_word |= (byte as u32) << (8 * self.current_word_fill);
-                self.current_word_fill += 1;
-                if self.current_word_fill >= 4 {
-                    if self.odd {
-                        self.checksum = self.checksum.wrapping_add(self.current_word);
-                    } else {
-                        self.checksum ^= self.current_word;
-                    }
-                    self.current_word = 0;
-                    self.current_word_fill = 0;
-                    self.odd = !self.odd;
-                }
+/// Checksum stored in header and the one computed from contents
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChecksumCheck {
+    pub expected: u32,
+    pub computed: u32,
+}
+
+impl ChecksumCheck {
+    pub fn matches(&self) -> bool {
+        self.expected == self.computed
+    }
+}
+
+/// Results of checksum verification of decompressed file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChecksumReport {
+    /// Checksum of compressed data
+    pub deobfuscation: ChecksumCheck,
+    /// Checksum of uncompressed data, not checked for uncompressed
+    /// files
+    pub decompression: Option<ChecksumCheck>,
+}
+
+impl ChecksumReport {
+    pub fn matches(&self) -> bool {
+        self.deobfuscation.matches() && self.decompression.is_none_or(|c| c.matches())
+    }
+
+    /// Returns first mismatching checksum as error
+    pub fn verify(&self) -> Result<(), DecompressError> {
+        let ChecksumCheck { expected, computed } = self.deobfuscation;
+        if !self.deobfuscation.matches() {
+            return Err(DecompressError::DeobfuscateChecksumNotMatch { expected, computed });
+        }
+        match self.decompression {
+            Some(ChecksumCheck { expected, computed }) if expected != computed => {
+                Err(DecompressError::DecompressChecksumNonMatch { expected, computed })
             }
+            _ => Ok(()),
         }
-        result
     }
 }
 
-/// Wraps reader with a reader that calculates checksum
-fn checksummed<R: Read>(reader: R) -> ChecksummingReader<R> {
-    ChecksummingReader {
-        reader,
-        checksum: 0,
-        odd: false,
-        current_word: 0,
-        current_word_fill: 0,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChecksumMode {
+    /// Fail with error on checksum mismatch
+    #[default]
+    Strict,
+    /// Return decompressed data even if checksums do not match, see
+    /// [Decompressed::checksums]
+    Lenient,
+}
+
+#[derive(Debug, Clone)]
+pub struct DecompressOptions {
+    pub checksums: ChecksumMode,
+    /// Maximum unpacked size declared in header, checked before
+    /// anything is allocated for output
+    pub max_output_size: Option<u64>,
+    /// Maximum ratio of unpacked size to compressed size
+    pub max_ratio: Option<u32>,
+}
+
+/// Default limit of unpacked size, well above sizes of game files
+pub const DEFAULT_MAX_OUTPUT_SIZE: u64 = 256 * 1024 * 1024;
+
+/// Default limit of compression ratio, well above the highest ratio
+/// of LZSS (17 bytes from 17 bits)
+pub const DEFAULT_MAX_RATIO: u32 = 64;
+
+impl Default for DecompressOptions {
+    fn default() -> Self {
+        DecompressOptions {
+            checksums: ChecksumMode::Strict,
+            max_output_size: Some(DEFAULT_MAX_OUTPUT_SIZE),
+            max_ratio: Some(DEFAULT_MAX_RATIO),
+        }
     }
 }
 
-#[deprecated]
-fn checksum<R: Read>(reader: R) -> u32 {
-    let mut checksummer = checksummed(reader);
-    std::io::copy(&mut checksummer, &mut std::io::sink()).unwrap();
-    checksummer.checksum
+impl DecompressOptions {
+    /// Checks unpacked size declared in `header` against
+    /// `max_output_size`, uncompressed files are never larger than
+    /// input
+    fn check_unpacked_size(&self, header: &Header) -> Result<(), DecompressError> {
+        let size = header.unpacked_size as u64;
+        match self.max_output_size {
+            Some(limit) if header.compression != CompressionType::Uncompressed && size > limit => {
+                Err(DecompressError::OutputTooLarge { size, limit })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn check_ratio(&self, size: u64, compressed_size: u64) -> Result<(), DecompressError> {
+        match self.max_ratio {
+            Some(limit) if size > limit as u64 * compressed_size => {
+                Err(DecompressError::RatioTooLarge {
+                    size,
+                    compressed_size,
+                    limit,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
 }
 
-fn deobfuscate(input: &mut [u8]) -> Result<Vec<u8>, DecompressError> {
+#[derive(Debug)]
+pub struct Decompressed {
+    pub data: Vec<u8>,
+    pub checksums: ChecksumReport,
+}
+
+/// Deobfuscates `input`, computing checksum of compressed data
+fn deobfuscate(input: &[u8]) -> Result<(Vec<u8>, ChecksumCheck), DecompressError> {
     let deobfuscated = obfuscation::deobfuscate(input)?;
     let header = Header::from_bytes(&deobfuscated)?;
-    if header.checksum_deobfuscated == checksum(&deobfuscated[HEADER_SIZE..]) {
-        Ok(deobfuscated)
-    } else {
-        Err(DecompressError::DeobfuscateChecksumNotMatch)
-    }
+    let check = ChecksumCheck {
+        expected: header.checksum_deobfuscated,
+        computed: Checksum::of(&deobfuscated[HEADER_SIZE..]),
+    };
+    Ok((deobfuscated, check))
+}
+
+/// Computes uncompressed checksum of `data` decompressed from body
+fn checked(header: &Header, data: Vec<u8>) -> (Vec<u8>, Option<ChecksumCheck>) {
+    let check = ChecksumCheck {
+        expected: header.checksum_uncompressed,
+        computed: Checksum::of(&data),
+    };
+    (data, Some(check))
 }
 
-fn lzss_decompress(input: &[u8]) -> Result<Vec<u8>, DecompressError> {
-    let header = Header::from_bytes(input)?;
+pub fn decompress(input: &mut [u8]) -> Result<Vec<u8>, DecompressError> {
+    decompress_with_options(input, &DecompressOptions::default()).map(|result| result.data)
+}
 
-    let mut buffer = Vec::with_capacity(header.unpacked_size as usize);
-    let mut compressed_reader = checksummed(
-        compression::decompress(&input[HEADER_SIZE..]).take(header.unpacked_size as u64),
-    );
-    compressed_reader.read_to_end(&mut buffer)?;
+pub fn decompress_with_options(
+    input: &[u8],
+    options: &DecompressOptions,
+) -> Result<Decompressed, DecompressError> {
+    if input.len() < 4 + HEADER_SIZE {
+        return Err(DecompressError::ContentTooSmall);
+    }
+    let (output, deobfuscation) = deobfuscate(input)?;
+    let strict = options.checksums == ChecksumMode::Strict;
+    if strict && !deobfuscation.matches() {
+        let ChecksumCheck { expected, computed } = deobfuscation;
+        return Err(DecompressError::DeobfuscateChecksumNotMatch { expected, computed });
+    }
 
-    if header.checksum_uncompressed == compressed_reader.checksum {
-        Ok(buffer)
-    } else {
-        Err(DecompressError::DecompressChecksumNonMatch)
+    let (data, decompression) = unpack(&output, options)?;
+    let checksums = ChecksumReport {
+        deobfuscation,
+        decompression,
+    };
+    if strict {
+        checksums.verify()?;
     }
+    Ok(Decompressed { data, checksums })
 }
 
-pub fn decompress(input: &mut [u8]) -> Result<Vec<u8>, DecompressError> {
-    if input.len() <= 20 {
-        return Err(DecompressError::ContentTooSmall);
+/// Decompresses deobfuscated file contents, starting with header
+fn unpack(
+    output: &[u8],
+    options: &DecompressOptions,
+) -> Result<(Vec<u8>, Option<ChecksumCheck>), DecompressError> {
+    let header = Header::from_bytes(output)?;
+    let body = &output[HEADER_SIZE..];
+    if header.compression != CompressionType::Uncompressed {
+        options.check_unpacked_size(&header)?;
+        options.check_ratio(header.unpacked_size as u64, body.len() as u64)?;
     }
-    let output = deobfuscate(input)?;
-    let header = Header::from_bytes(&output)?;
+    let unpacked_size = header.unpacked_size as usize;
     match header.compression {
-        CompressionType::Uncompressed => Ok(output[HEADER_SIZE..].to_vec()),
-        CompressionType::LZSS => lzss_decompress(&output),
+        CompressionType::Uncompressed => Ok((body.to_vec(), None)),
+        CompressionType::LZSS => Ok(checked(
+            &header,
+            compression::decompress_slice(body, unpacked_size)?,
+        )),
         _ => Err(DecompressError::CompressionNotSupported),
     }
 }
 
+#[cfg(feature = "std")]
 pub fn read_decompressed<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, DecompressError> {
-    let mut f = File::open(&path)?;
+    let mut f = File::open(path)?;
     let mut buffer = Vec::new();
     f.read_to_end(&mut buffer)?;
     decompress(&mut buffer)
 }
 
+/// Information from header of obfuscated file
+#[derive(Debug, Clone)]
+pub struct FileInfo {
+    /// Seed of obfuscation pseudo-random generator
+    pub seed: u32,
+    pub unpacked_size: u32,
+    /// Checksum of compressed data, as stored in header
+    pub checksum_deobfuscated: u32,
+    /// Checksum of uncompressed data, as stored in header
+    pub checksum_uncompressed: u32,
+    pub compression: CompressionType,
+    /// Whether compressed data matches `checksum_deobfuscated`
+    pub deobfuscation_checksum_matches: bool,
+}
+
+/// Deobfuscates file from `reader` and reads its header, without
+/// decompressing
+///
+/// Accepts byte slices as well as files, see also [inspect_file] and
+/// [inspect_slice].
+#[cfg(feature = "std")]
+pub fn inspect<R: Read>(reader: R) -> Result<FileInfo, DecompressError> {
+    let mut source =
+        obfuscation::DeobfuscatingReader::new(reader).map_err(DecompressError::from_header_read)?;
+    let seed = source.seed();
+    let mut header_bytes = [0; HEADER_SIZE];
+    source
+        .read_exact(&mut header_bytes)
+        .map_err(DecompressError::from_header_read)?;
+    let header = Header::from_bytes(&header_bytes)?;
+
+    let mut body = ChecksummingReader::new(source);
+    std::io::copy(&mut body, &mut std::io::sink())?;
+
+    Ok(FileInfo {
+        seed,
+        unpacked_size: header.unpacked_size,
+        checksum_deobfuscated: header.checksum_deobfuscated,
+        checksum_uncompressed: header.checksum_uncompressed,
+        compression: header.compression,
+        deobfuscation_checksum_matches: body.checksum() == header.checksum_deobfuscated,
+    })
+}
+
+#[cfg(feature = "std")]
+pub fn inspect_file<P: AsRef<Path>>(path: P) -> Result<FileInfo, DecompressError> {
+    inspect(std::io::BufReader::new(File::open(path)?))
+}
+
+/// Same as [inspect] for contents already in memory, available
+/// without `std` feature
+pub fn inspect_slice(input: &[u8]) -> Result<FileInfo, DecompressError> {
+    if input.len() < 4 + HEADER_SIZE {
+        return Err(DecompressError::ContentTooSmall);
+    }
+    let seed = obfuscation::seed(input)?;
+    let (deobfuscated, check) = deobfuscate(input)?;
+    let header = Header::from_bytes(&deobfuscated)?;
+    Ok(FileInfo {
+        seed,
+        unpacked_size: header.unpacked_size,
+        checksum_deobfuscated: header.checksum_deobfuscated,
+        checksum_uncompressed: header.checksum_uncompressed,
+        compression: header.compression,
+        deobfuscation_checksum_matches: check.matches(),
+    })
+}
+
+#[derive(Debug, Clone)]
+pub struct CompressOptions {
+    /// Seed of obfuscation pseudo-random generator, stored in the
+    /// first 4 bytes of the file
+    pub seed: u32,
+    /// Compression type stored in header, [Compression::LZSS] by
+    /// default
+    pub compression: Compression,
+    /// Used for [Compression::LZSS] only
+    pub level: CompressionLevel,
+}
+
+impl Default for CompressOptions {
+    fn default() -> Self {
+        CompressOptions {
+            seed: 0,
+            compression: Compression::LZSS,
+            level: CompressionLevel::default(),
+        }
+    }
+}
+
+/// Compresses and obfuscates `data` into format readable by the game
+/// and [decompress]
+pub fn compress(data: &[u8], options: &CompressOptions) -> Vec<u8> {
+    let body = encode(data, options.compression, options.level);
+    pack(data, &body, options.compression.into(), options.seed)
+}
+
+/// Compressed body of file
+fn encode(data: &[u8], compression: Compression, level: CompressionLevel) -> Vec<u8> {
+    match compression {
+        Compression::Uncompressed => data.to_vec(),
+        Compression::LZSS => compression::compress_with_level(data, level),
+    }
+}
+
+/// Prepends header to already compressed `body` and obfuscates the
+/// result
+fn pack(data: &[u8], body: &[u8], compression: CompressionType, seed: u32) -> Vec<u8> {
+    let header = Header {
+        unpacked_size: data.len() as u32,
+        checksum_deobfuscated: Checksum::of(body),
+        checksum_uncompressed: Checksum::of(data),
+        compression,
+    };
+
+    let mut output = Vec::with_capacity(HEADER_SIZE + body.len());
+    output.extend_from_slice(&header.to_bytes());
+    output.extend_from_slice(body);
+    obfuscation::obfuscate(&output, seed)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::test_utils::*;
+    use alloc::vec;
+
+    #[cfg(feature = "std")]
     #[test]
     #[ignore]
     fn test_decompress() {
-        let decoded = read_decompressed(&test_file_path("Realms/Celtic/Forest/CFsec50.map"));
+        let decoded = read_decompressed(test_file_path("Realms/Celtic/Forest/CFsec50.map"));
         assert!(decoded.is_ok(), "Decompress failed: {:?}", decoded);
         assert_eq!(
             6,
@@ -225,10 +576,244 @@ mod tests {
     #[test]
     #[ignore]
     fn test_too_short() {
-        let decoded = decompress(&mut vec![0; 10]);
+        let decoded = decompress(&mut [0; 10]);
         match decoded.unwrap_err() {
             DecompressError::ContentTooSmall => (),
             x => panic!("Invalid error {:?}", x),
         }
     }
+
+    #[test]
+    fn test_compress_then_decompress() {
+        let source: Vec<u8> = b"The quick brown fox jumps over the lazy dog"
+            .iter()
+            .cycle()
+            .take(1000)
+            .copied()
+            .collect();
+        let mut compressed = compress(
+            &source,
+            &CompressOptions {
+                seed: 123456,
+                ..Default::default()
+            },
+        );
+        assert_eq!(&123456u32.to_le_bytes(), &compressed[..4]);
+        assert_eq!(source, decompress(&mut compressed).unwrap());
+    }
+
+    #[test]
+    fn test_compress_then_decompress_empty() {
+        for compression in [Compression::LZSS, Compression::Uncompressed] {
+            let mut compressed = compress(
+                &[],
+                &CompressOptions {
+                    compression,
+                    ..Default::default()
+                },
+            );
+            assert_eq!(4 + HEADER_SIZE, compressed.len());
+            assert!(decompress(&mut compressed).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_compress_types() {
+        let source = sample();
+        let compressed = compress(
+            &source,
+            &CompressOptions {
+                compression: Compression::Uncompressed,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            CompressionType::Uncompressed,
+            inspect_slice(&compressed).unwrap().compression
+        );
+        let result = decompress_with_options(&compressed, &DecompressOptions::default());
+        assert_eq!(source, result.unwrap().data);
+    }
+
+    #[test]
+    fn test_decompress_rle_not_supported() {
+        let source = sample();
+        let mut packed = pack(&source, &source, CompressionType::RLE, 42);
+        assert!(matches!(
+            decompress(&mut packed).unwrap_err(),
+            DecompressError::CompressionNotSupported
+        ));
+    }
+
+    #[test]
+    fn test_decompress_checksum_mismatch() {
+        let source = sample();
+        let body = compression::compress(&source);
+        let mut other = source.clone();
+        other[500] = 0xff;
+        let mut packed = pack(&other, &body, CompressionType::LZSS, 42);
+        match decompress(&mut packed).unwrap_err() {
+            DecompressError::DecompressChecksumNonMatch { expected, computed } => {
+                assert_ne!(expected, computed)
+            }
+            x => panic!("Invalid error {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_decompress_truncated_body() {
+        let source: Vec<u8> = (0..1000u32).map(|i| (i * 7 / 5 % 251) as u8).collect();
+        let body = compression::compress(&source);
+        let mut packed = pack(&source, &body[..body.len() / 2], CompressionType::LZSS, 42);
+        match decompress(&mut packed).unwrap_err() {
+            DecompressError::PrematureEnd {
+                context: Some(context),
+            } => {
+                assert!(context.produced < 1000);
+                assert_eq!(Some(1000), context.unpacked_size);
+                assert!(context.bit_offset <= body.len() as u64 / 2 * 8);
+                assert!(context.last_token.is_some());
+            }
+            x => panic!("Invalid error {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_decompress_lenient() {
+        let source = sample();
+        let body = compression::compress(&source);
+        let mut other = source.clone();
+        other[500] = 0xff;
+        let packed = pack(&other, &body, CompressionType::LZSS, 42);
+
+        let lenient = DecompressOptions {
+            checksums: ChecksumMode::Lenient,
+            ..Default::default()
+        };
+        let result = decompress_with_options(&packed, &lenient).unwrap();
+        assert_eq!(source, result.data);
+        assert!(result.checksums.deobfuscation.matches());
+        let decompression = result.checksums.decompression.unwrap();
+        assert!(!decompression.matches());
+        assert!(!result.checksums.matches());
+        match decompress_with_options(&packed, &DecompressOptions::default()).unwrap_err() {
+            DecompressError::DecompressChecksumNonMatch { expected, computed } => {
+                assert_eq!(decompression, ChecksumCheck { expected, computed })
+            }
+            x => panic!("Invalid error {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_decompress_lenient_deobfuscation() {
+        let (_, mut compressed) = compressed_sample(1);
+        compressed[30] ^= 0x01;
+
+        let lenient = DecompressOptions {
+            checksums: ChecksumMode::Lenient,
+            ..Default::default()
+        };
+        let result = decompress_with_options(&compressed, &lenient).unwrap();
+        assert!(!result.checksums.deobfuscation.matches());
+        assert!(matches!(
+            decompress(&mut compressed).unwrap_err(),
+            DecompressError::DeobfuscateChecksumNotMatch { .. }
+        ));
+    }
+
+    #[test]
+    fn test_decompress_output_too_large() {
+        let source = vec![0; 100000];
+        let compressed = compress(
+            &source,
+            &CompressOptions {
+                seed: 1,
+                ..Default::default()
+            },
+        );
+        let options = DecompressOptions {
+            max_output_size: Some(99999),
+            ..Default::default()
+        };
+        match decompress_with_options(&compressed, &options).unwrap_err() {
+            DecompressError::OutputTooLarge { size, limit } => {
+                assert_eq!((100000, 99999), (size, limit))
+            }
+            x => panic!("Invalid error {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_decompress_forged_unpacked_size() {
+        let source = b"The quick brown fox jumps over the lazy dog";
+        let body = compression::compress(source);
+        let mut deobfuscated =
+            obfuscation::deobfuscate(&pack(source, &body, CompressionType::LZSS, 1)).unwrap();
+        deobfuscated[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let mut packed = obfuscation::obfuscate(&deobfuscated, 1);
+
+        match decompress(&mut packed).unwrap_err() {
+            DecompressError::OutputTooLarge { size, .. } => assert_eq!(u32::MAX as u64, size),
+            x => panic!("Invalid error {:?}", x),
+        }
+        let options = DecompressOptions {
+            max_output_size: None,
+            ..Default::default()
+        };
+        match decompress_with_options(&packed, &options).unwrap_err() {
+            DecompressError::RatioTooLarge {
+                compressed_size, ..
+            } => assert_eq!(body.len() as u64, compressed_size),
+            x => panic!("Invalid error {:?}", x),
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_inspect() {
+        let (source, mut compressed) = compressed_sample(31337);
+
+        let info = inspect(&compressed[..]).unwrap();
+        assert_eq!(31337, info.seed);
+        assert_eq!(source.len() as u32, info.unpacked_size);
+        assert_eq!(CompressionType::LZSS, info.compression);
+        assert!(info.deobfuscation_checksum_matches);
+
+        compressed[30] ^= 0x10;
+        let info = inspect(&compressed[..]).unwrap();
+        assert_eq!(source.len() as u32, info.unpacked_size);
+        assert!(!info.deobfuscation_checksum_matches);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_inspect_too_small() {
+        match inspect(&[1, 2, 3, 4, 5][..]).unwrap_err() {
+            DecompressError::ContentTooSmall => (),
+            x => panic!("Invalid error {:?}", x),
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_inspect_slice() {
+        let (_, mut compressed) = compressed_sample(31337);
+        for _ in 0..2 {
+            let info = inspect_slice(&compressed).unwrap();
+            let streamed = inspect(&compressed[..]).unwrap();
+            assert_eq!(
+                (streamed.seed, streamed.unpacked_size, streamed.compression),
+                (info.seed, info.unpacked_size, info.compression)
+            );
+            assert_eq!(
+                streamed.deobfuscation_checksum_matches,
+                info.deobfuscation_checksum_matches
+            );
+            compressed[30] ^= 0x10;
+        }
+        assert!(matches!(
+            inspect_slice(&[1, 2, 3, 4, 5]).unwrap_err(),
+            DecompressError::ContentTooSmall
+        ));
+    }
 }
@@ -7,29 +7,44 @@
 // Author: Nikita Sadkov
 // License: GPL2
 
+//! Obfuscation with XOR keystream
+//!
+//! Obfuscated data is prefixed with 4-byte little-endian seed of the
+//! [Keystream]. Full 4-byte words are XORed with whole keystream values,
+//! trailing bytes with lowest byte of one value each.
+
 #![allow(clippy::cast_lossless)]
 
-use std::{convert::TryInto, error::Error, fmt::Display};
+use alloc::vec::Vec;
+use core::{convert::TryInto, error::Error, fmt::Display};
+#[cfg(feature = "std")]
+use std::io::{self, Read, Write};
 
 #[derive(Debug, PartialEq)]
 pub struct InputTooSmall;
 
 impl Display for InputTooSmall {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "input should be at least 4 bytes")
     }
 }
 impl Error for InputTooSmall {}
 
-struct PRNG {
+/// Keystream XORed with obfuscated data
+///
+/// Lagged Fibonacci generator over a table of 250 words seeded with
+/// an LCG, each value is XOR of entries 0 and 103 positions back.
+/// Endless, [Iterator::next] never returns `None`.
+#[derive(Clone)]
+pub struct Keystream {
     table: [u32; 250],
     i: usize,
     j: usize,
 }
 
-impl PRNG {
+impl Keystream {
     pub fn new(seed: u32) -> Self {
-        PRNG {
+        Keystream {
             table: Self::table_from_seed(seed),
             i: 0,
             j: 103,
@@ -67,10 +82,10 @@ impl PRNG {
     }
 }
 
-impl Iterator for PRNG {
+impl Iterator for Keystream {
     type Item = u32;
 
-    fn next(self: &mut Self) -> Option<u32> {
+    fn next(&mut self) -> Option<u32> {
         let value = self.table[self.i] ^ self.table[self.j];
         self.table[self.i] = value;
 
@@ -82,21 +97,28 @@ impl Iterator for PRNG {
 }
 
 fn process(input: &[u8], seed: u32, output: &mut Vec<u8>) {
-    let mut prng = PRNG::new(seed);
+    let mut keystream = Keystream::new(seed);
     let chunks_iter = input.chunks_exact(4);
     let remainder = chunks_iter.remainder();
 
     for chunk in chunks_iter {
-        let current = u32::from_le_bytes(chunk.try_into().unwrap()) ^ prng.next().unwrap();
+        let current = u32::from_le_bytes(chunk.try_into().unwrap()) ^ keystream.next().unwrap();
         output.extend_from_slice(&u32::to_le_bytes(current));
     }
     for chunk in remainder.iter() {
-        output.push(*chunk ^ prng.next().unwrap() as u8);
+        output.push(*chunk ^ keystream.next().unwrap() as u8);
     }
 }
 
+/// Seed stored in the first 4 bytes of obfuscated `input`
+pub fn seed(input: &[u8]) -> Result<u32, InputTooSmall> {
+    Ok(u32::from_le_bytes(
+        input.get(..4).ok_or(InputTooSmall)?.try_into().unwrap(),
+    ))
+}
+
 pub fn deobfuscate(input: &[u8]) -> Result<Vec<u8>, InputTooSmall> {
-    let seed = u32::from_le_bytes(input.get(..4).ok_or(InputTooSmall)?.try_into().unwrap());
+    let seed = seed(input)?;
     let mut result = Vec::with_capacity(input.len());
     process(&input[4..], seed, &mut result);
     Ok(result)
@@ -109,9 +131,203 @@ pub fn obfuscate(input: &[u8], seed: u32) -> Vec<u8> {
     result
 }
 
+#[cfg(feature = "std")]
+const BUFFER_SIZE: usize = 0x1000;
+
+/// Reader that deobfuscates data as it's read
+///
+/// Full 4-byte words are XORed with whole keystream values, trailing
+/// bytes at the end of input with lowest byte of one value each, so
+/// up to 3 bytes are held back until end of input is reached.
+#[cfg(feature = "std")]
+pub struct DeobfuscatingReader<R: Read> {
+    reader: R,
+    seed: u32,
+    keystream: Keystream,
+    buffer: Vec<u8>,
+    /// Start of deobfuscated bytes not yet returned
+    start: usize,
+    /// End of deobfuscated bytes, followed by incomplete word
+    deobfuscated_end: usize,
+    /// End of bytes read from `reader`
+    end: usize,
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> DeobfuscatingReader<R> {
+    /// Reads seed from first 4 bytes of `reader`
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut seed_bytes = [0; 4];
+        reader.read_exact(&mut seed_bytes)?;
+        let seed = u32::from_le_bytes(seed_bytes);
+        Ok(DeobfuscatingReader {
+            reader,
+            seed,
+            keystream: Keystream::new(seed),
+            buffer: vec![0; BUFFER_SIZE],
+            start: 0,
+            deobfuscated_end: 0,
+            end: 0,
+        })
+    }
+
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+
+    fn fill_buffer(&mut self) -> io::Result<()> {
+        while self.start == self.deobfuscated_end {
+            self.buffer.copy_within(self.deobfuscated_end..self.end, 0);
+            self.end -= self.deobfuscated_end;
+            self.start = 0;
+            self.deobfuscated_end = 0;
+
+            let bytes_read = self.reader.read(&mut self.buffer[self.end..])?;
+            if bytes_read == 0 {
+                for byte in &mut self.buffer[..self.end] {
+                    *byte ^= self.keystream.next().unwrap() as u8;
+                }
+                self.deobfuscated_end = self.end;
+                return Ok(());
+            }
+            self.end += bytes_read;
+            self.deobfuscated_end = self.end / 4 * 4;
+            for chunk in self.buffer[..self.deobfuscated_end].chunks_exact_mut(4) {
+                let value = u32::from_le_bytes((&*chunk).try_into().unwrap())
+                    ^ self.keystream.next().unwrap();
+                chunk.copy_from_slice(&value.to_le_bytes());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> Read for DeobfuscatingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.fill_buffer()?;
+        let size = buf.len().min(self.deobfuscated_end - self.start);
+        buf[..size].copy_from_slice(&self.buffer[self.start..self.start + size]);
+        self.start += size;
+        Ok(size)
+    }
+}
+
+/// Writer that obfuscates data as it's written
+///
+/// Writes the seed first, then full 4-byte words as soon as they are
+/// complete. Up to 3 trailing bytes can only be written once it's known
+/// they are the end of data, so they are held back until [finish] is
+/// called or the writer is dropped. [Write::flush] doesn't write them.
+///
+/// [finish]: ObfuscatingWriter::finish
+#[cfg(feature = "std")]
+pub struct ObfuscatingWriter<W: Write> {
+    writer: Option<W>,
+    keystream: Keystream,
+    buffer: Vec<u8>,
+    /// Bytes of incomplete word not yet written
+    pending: [u8; 4],
+    pending_size: usize,
+}
+
+#[cfg(feature = "std")]
+impl<W: Write> ObfuscatingWriter<W> {
+    /// Writes `seed` to first 4 bytes of `writer`
+    pub fn new(mut writer: W, seed: u32) -> io::Result<Self> {
+        writer.write_all(&seed.to_le_bytes())?;
+        Ok(ObfuscatingWriter {
+            writer: Some(writer),
+            keystream: Keystream::new(seed),
+            buffer: Vec::with_capacity(BUFFER_SIZE),
+            pending: [0; 4],
+            pending_size: 0,
+        })
+    }
+
+    fn writer(&mut self) -> &mut W {
+        self.writer.as_mut().expect("writer used after finish")
+    }
+
+    /// Writes held back trailing bytes and returns inner writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_remainder()?;
+        let mut writer = self.writer.take().unwrap();
+        writer.flush()?;
+        Ok(writer)
+    }
+
+    fn write_remainder(&mut self) -> io::Result<()> {
+        let mut remainder = [0; 3];
+        for (output, byte) in remainder.iter_mut().zip(&self.pending[..self.pending_size]) {
+            *output = byte ^ self.keystream.next().unwrap() as u8;
+        }
+        let size = self.pending_size;
+        self.pending_size = 0;
+        self.writer().write_all(&remainder[..size])
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: Write> Write for ObfuscatingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut input = buf;
+        self.buffer.clear();
+        if self.pending_size > 0 {
+            let size = input.len().min(4 - self.pending_size);
+            self.pending[self.pending_size..self.pending_size + size]
+                .copy_from_slice(&input[..size]);
+            self.pending_size += size;
+            input = &input[size..];
+            if self.pending_size < 4 {
+                return Ok(buf.len());
+            }
+            let value = u32::from_le_bytes(self.pending) ^ self.keystream.next().unwrap();
+            self.buffer.extend_from_slice(&value.to_le_bytes());
+            self.pending_size = 0;
+        }
+
+        let chunks_iter = input.chunks_exact(4);
+        let remainder = chunks_iter.remainder();
+        for chunk in chunks_iter {
+            let value =
+                u32::from_le_bytes(chunk.try_into().unwrap()) ^ self.keystream.next().unwrap();
+            self.buffer.extend_from_slice(&value.to_le_bytes());
+        }
+        self.pending[..remainder.len()].copy_from_slice(remainder);
+        self.pending_size = remainder.len();
+
+        let writer = self.writer.as_mut().expect("writer used after finish");
+        writer.write_all(&self.buffer)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer().flush()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: Write> Drop for ObfuscatingWriter<W> {
+    fn drop(&mut self) {
+        if self.writer.is_some() {
+            // Errors can't be reported here, use finish to handle them
+            let _ = self.write_remainder();
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use super::{deobfuscate, obfuscate, InputTooSmall};
+    use super::{deobfuscate, obfuscate, InputTooSmall, Keystream};
+    #[cfg(feature = "std")]
+    use super::{DeobfuscatingReader, ObfuscatingWriter};
+    use alloc::vec::Vec;
+    #[cfg(feature = "std")]
+    use std::io::{Read, Write};
 
     #[test]
     fn test_obfuscate_then_deobfuscate() {
@@ -136,6 +352,81 @@ mod test {
         assert_eq!(result, expected);
     }
 
+    /// Reader returning at most 3 bytes per read
+    #[cfg(feature = "std")]
+    struct Trickle<'a>(&'a [u8]);
+
+    #[cfg(feature = "std")]
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let size = buf.len().min(self.0.len()).min(3);
+            buf[..size].copy_from_slice(&self.0[..size]);
+            self.0 = &self.0[size..];
+            Ok(size)
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_deobfuscating_reader() {
+        for length in [0, 1, 3, 4, 5, 4095, 4096, 4097, 10001] {
+            let source: Vec<u8> = (0..length).map(|i| (i % 253) as u8).collect();
+            let obfuscated = obfuscate(&source, 98765);
+
+            let mut result = Vec::new();
+            DeobfuscatingReader::new(&obfuscated[..])
+                .unwrap()
+                .read_to_end(&mut result)
+                .unwrap();
+            assert_eq!(source, result, "length {}", length);
+
+            let mut result = Vec::new();
+            DeobfuscatingReader::new(Trickle(&obfuscated))
+                .unwrap()
+                .read_to_end(&mut result)
+                .unwrap();
+            assert_eq!(source, result, "length {} in small reads", length);
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_obfuscating_writer() {
+        for length in [0, 1, 3, 4, 5, 4095, 4096, 4097, 10001] {
+            let source: Vec<u8> = (0..length).map(|i| (i % 253) as u8).collect();
+            let expected = obfuscate(&source, 98765);
+
+            let mut writer = ObfuscatingWriter::new(Vec::new(), 98765).unwrap();
+            writer.write_all(&source).unwrap();
+            assert_eq!(expected, writer.finish().unwrap(), "length {}", length);
+
+            for chunk_size in [1, 2, 3, 5, 7] {
+                let mut result = Vec::new();
+                let mut writer = ObfuscatingWriter::new(&mut result, 98765).unwrap();
+                for chunk in source.chunks(chunk_size) {
+                    writer.write_all(chunk).unwrap();
+                }
+                drop(writer);
+                assert_eq!(
+                    expected, result,
+                    "length {} in chunks of {}",
+                    length, chunk_size
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_keystream() {
+        let source = [0u8; 12];
+        let obfuscated = obfuscate(&source, 4242);
+        let keystream: Vec<u8> = Keystream::new(4242)
+            .take(3)
+            .flat_map(u32::to_le_bytes)
+            .collect();
+        assert_eq!(&obfuscated[4..], &keystream[..]);
+    }
+
     #[test]
     fn test_deobfuscate_empty() {
         assert_eq!(InputTooSmall, deobfuscate(&[]).unwrap_err());
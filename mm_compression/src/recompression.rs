@@ -0,0 +1,120 @@
+//! Verification of byte-exact recompression of game files
+
+use crate::compression::CompressionLevel;
+use crate::{
+    decompress_with_options, encode, obfuscation, pack, Compression, CompressionType,
+    DecompressError, DecompressOptions, Header, HEADER_SIZE,
+};
+
+/// First difference between original file and its recompressed
+/// version
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Divergence {
+    /// Offset in file, including seed
+    pub offset: usize,
+    /// Byte of original file, `None` if it's shorter
+    pub original: Option<u8>,
+    /// Byte of recompressed file, `None` if it's shorter
+    pub recompressed: Option<u8>,
+}
+
+/// Decompresses `original` file and compresses it back with
+/// [CompressionLevel::Faithful], the same seed and the same
+/// compression type
+///
+/// Returns first byte where recompressed file differs from the
+/// original, or `None` if they are identical.
+pub fn verify_recompression(original: &[u8]) -> Result<Option<Divergence>, DecompressError> {
+    let seed = obfuscation::seed(original)?;
+    let data = decompress_with_options(original, &DecompressOptions::default())?.data;
+    let deobfuscated_header = obfuscation::deobfuscate(&original[..HEADER_SIZE + 4])?;
+    let header = Header::from_bytes(&deobfuscated_header)?;
+
+    let compression = match header.compression {
+        CompressionType::Uncompressed => Compression::Uncompressed,
+        CompressionType::LZSS => Compression::LZSS,
+        CompressionType::RLE | CompressionType::Unknown => {
+            return Err(DecompressError::CompressionNotSupported)
+        }
+    };
+    let body = encode(&data, compression, CompressionLevel::Faithful);
+    let recompressed = pack(&data, &body, header.compression, seed);
+
+    let length = original.len().max(recompressed.len());
+    Ok((0..length)
+        .map(|offset| Divergence {
+            offset,
+            original: original.get(offset).copied(),
+            recompressed: recompressed.get(offset).copied(),
+        })
+        .find(|divergence| divergence.original != divergence.recompressed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "std")]
+    use crate::test_utils::*;
+    use crate::{compress, CompressOptions};
+    use alloc::vec::Vec;
+
+    #[cfg(feature = "std")]
+    #[test]
+    #[ignore]
+    fn test_verify_original_files() {
+        for path in [
+            "Realms/Celtic/Forest/CFsec50.map",
+            "Realms/Celtic/Forest/CFSec10.map",
+        ] {
+            let original = test_file_contents(path);
+            assert_eq!(None, verify_recompression(&original).unwrap(), "{}", path);
+        }
+    }
+
+    /// Only checks that verification matches file produced by the same
+    /// level, see [test_verify_original_files] for game files
+    #[test]
+    fn test_verify_same_level() {
+        let source: Vec<u8> = (0..3000u32).map(|i| (i * i / 7 % 17) as u8).collect();
+        let options = CompressOptions {
+            seed: 4242,
+            level: CompressionLevel::Faithful,
+            ..Default::default()
+        };
+        assert_eq!(
+            None,
+            verify_recompression(&compress(&source, &options)).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_verify_diverging() {
+        // Fast level references the nearest "1, 2", faithful the first
+        let source = [1, 2, 9, 1, 2, 8, 1, 2];
+        let options = CompressOptions {
+            seed: 4242,
+            level: CompressionLevel::Fast,
+            ..Default::default()
+        };
+        let original = compress(&source, &options);
+        let divergence = verify_recompression(&original).unwrap().unwrap();
+        // Seed is reused
+        assert!(divergence.offset >= 4);
+        assert_eq!(Some(original[divergence.offset]), divergence.original);
+        assert_ne!(divergence.original, divergence.recompressed);
+    }
+
+    #[test]
+    fn test_verify_uncompressed() {
+        let source: Vec<u8> = (0..3000u32).map(|i| (i / 13) as u8).collect();
+        let options = CompressOptions {
+            seed: 4242,
+            compression: Compression::Uncompressed,
+            ..Default::default()
+        };
+        assert_eq!(
+            None,
+            verify_recompression(&compress(&source, &options)).unwrap()
+        );
+    }
+}
@@ -1,23 +1,51 @@
+#[cfg(feature = "std")]
 use crate::read_decompressed;
+use crate::{compress, CompressOptions};
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
 use std::env;
+#[cfg(feature = "std")]
 use std::fs::File;
+#[cfg(feature = "std")]
 use std::io::prelude::*;
+#[cfg(feature = "std")]
 use std::path::{Path, PathBuf};
 
+#[cfg(feature = "std")]
 pub fn test_file_path(filename: &str) -> PathBuf {
     let prefix = env::var("MM_PATH").expect("MM_PATH must be specified to run tests");
     Path::new(&prefix).join(Path::new(filename))
 }
 
+#[cfg(feature = "std")]
 #[allow(dead_code)]
 pub fn test_file_contents(filename: &str) -> Vec<u8> {
     let mut buffer = Vec::new();
-    let mut file = File::open(&test_file_path(filename)).unwrap();
+    let mut file = File::open(test_file_path(filename)).unwrap();
     file.read_to_end(&mut buffer).unwrap();
     buffer
 }
 
+#[cfg(feature = "std")]
 #[allow(dead_code)]
 pub fn test_file_compressed_contents(filename: &str) -> Vec<u8> {
-    read_decompressed(&test_file_path(filename)).unwrap()
+    read_decompressed(test_file_path(filename)).unwrap()
+}
+
+/// Sample data spanning several LZSS windows
+pub fn sample() -> Vec<u8> {
+    (0..30000u32).map(|i| (i * i / 1000 % 256) as u8).collect()
+}
+
+/// [sample] and its LZSS compressed file obfuscated with `seed`
+pub fn compressed_sample(seed: u32) -> (Vec<u8>, Vec<u8>) {
+    let source = sample();
+    let compressed = compress(
+        &source,
+        &CompressOptions {
+            seed,
+            ..Default::default()
+        },
+    );
+    (source, compressed)
 }
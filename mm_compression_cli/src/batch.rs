@@ -0,0 +1,260 @@
+//! Processing of whole directory trees
+
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use mm_compression::FileKind;
+use rayon::prelude::*;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+pub type ProcessError = Box<dyn Error + Send + Sync>;
+
+/// Include and exclude glob patterns, matched case-insensitively
+/// against paths relative to source directory
+pub struct Filter {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl Filter {
+    /// Empty `include` includes all files
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self, globset::Error> {
+        fn build(patterns: &[String]) -> Result<GlobSet, globset::Error> {
+            let mut builder = GlobSetBuilder::new();
+            for pattern in patterns {
+                builder.add(glob(pattern)?);
+            }
+            builder.build()
+        }
+        fn glob(pattern: &str) -> Result<Glob, globset::Error> {
+            GlobBuilder::new(pattern)
+                .case_insensitive(true)
+                .literal_separator(false)
+                .build()
+        }
+
+        Ok(Filter {
+            include: match include {
+                [] => None,
+                patterns => Some(build(patterns)?),
+            },
+            exclude: build(exclude)?,
+        })
+    }
+
+    fn matches(&self, path: &Path) -> bool {
+        self.include.as_ref().is_none_or(|set| set.is_match(path)) && !self.exclude.is_match(path)
+    }
+}
+
+/// Counts of processed files and errors of failed ones
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub processed: usize,
+    /// Files not matching filter are not counted
+    pub skipped: usize,
+    pub failed: Vec<(PathBuf, ProcessError)>,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (path, error) in &self.failed {
+            writeln!(f, "{}: {}", path.display(), error)?;
+        }
+        write!(
+            f,
+            "{} processed, {} skipped, {} failed",
+            self.processed,
+            self.skipped,
+            self.failed.len()
+        )
+    }
+}
+
+/// Paths relative to `source` of files matching `filter`, and errors
+/// of entries which couldn't be read
+pub fn collect_files(source: &Path, filter: &Filter) -> (Vec<PathBuf>, Vec<walkdir::Error>) {
+    let mut files = Vec::new();
+    let mut errors = Vec::new();
+    for entry in WalkDir::new(source).follow_links(true).sort_by_file_name() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(error) => {
+                errors.push(error);
+                continue;
+            }
+        };
+        let relative = entry.path().strip_prefix(source).unwrap();
+        if entry.file_type().is_file() && filter.matches(relative) {
+            files.push(relative.to_path_buf());
+        }
+    }
+    (files, errors)
+}
+
+/// Deobfuscates and decompresses obfuscated game file, skipping plain
+/// and unknown ones
+pub fn decompress_game_file(contents: Vec<u8>) -> Result<Option<Vec<u8>>, ProcessError> {
+    let file = mm_compression::decode_game_file(contents)?;
+    Ok(match file.kind {
+        FileKind::Obfuscated(_) => Some(file.contents),
+        FileKind::Plain | FileKind::Unknown => None,
+    })
+}
+
+/// Applies `process` to contents of every file under `source`
+/// matching `filter` in parallel, writing results to the same relative
+/// paths under `destination`
+///
+/// `process` returns `None` to skip file. Directory entries which
+/// couldn't be read are reported as failed.
+pub fn process_tree<F>(source: &Path, destination: &Path, filter: &Filter, process: F) -> Summary
+where
+    F: Fn(Vec<u8>) -> Result<Option<Vec<u8>>, ProcessError> + Sync,
+{
+    let (files, errors) = collect_files(source, filter);
+    let results: Vec<_> = files
+        .into_par_iter()
+        .map(|relative| {
+            let result = process_file(source, destination, &relative, &process);
+            (relative, result)
+        })
+        .collect();
+
+    let mut summary = Summary::default();
+    for error in errors {
+        let path = error.path().unwrap_or(source).to_path_buf();
+        summary.failed.push((path, error.into()));
+    }
+    for (relative, result) in results {
+        match result {
+            Ok(true) => summary.processed += 1,
+            Ok(false) => summary.skipped += 1,
+            Err(error) => summary.failed.push((source.join(relative), error)),
+        }
+    }
+    summary
+}
+
+/// Returns whether file was written
+fn process_file<F>(
+    source: &Path,
+    destination: &Path,
+    relative: &Path,
+    process: &F,
+) -> Result<bool, ProcessError>
+where
+    F: Fn(Vec<u8>) -> Result<Option<Vec<u8>>, ProcessError>,
+{
+    let output = match process(fs::read(source.join(relative))?)? {
+        Some(output) => output,
+        None => return Ok(false),
+    };
+    let destination = destination.join(relative);
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(destination, output)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mm_compression::{compress, CompressOptions};
+
+    #[test]
+    fn test_filter() {
+        let filter =
+            Filter::new(&["*.map".into(), "Realms/**".into()], &["*sec9*".into()]).unwrap();
+        assert!(filter.matches(Path::new("CFsec50.MAP")));
+        assert!(filter.matches(Path::new("Realms/Celtic/CFsec50.map")));
+        assert!(filter.matches(Path::new("realms/readme.txt")));
+        assert!(!filter.matches(Path::new("CFsec9.map")));
+        assert!(!filter.matches(Path::new("sprites/a.spr")));
+
+        let filter = Filter::new(&[], &[]).unwrap();
+        assert!(filter.matches(Path::new("sprites/a.spr")));
+    }
+
+    #[test]
+    fn test_process_tree() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        let source = root.join("source");
+        let destination = root.join("destination");
+        fs::create_dir_all(source.join("nested")).unwrap();
+        fs::write(source.join("a.map"), b"a").unwrap();
+        fs::write(source.join("nested/b.map"), b"b").unwrap();
+        fs::write(source.join("nested/skip.map"), b"s").unwrap();
+        fs::write(source.join("nested/fail.map"), b"f").unwrap();
+        fs::write(source.join("excluded.txt"), b"x").unwrap();
+
+        let filter = Filter::new(&["*.map".into()], &[]).unwrap();
+        let summary = process_tree(&source, &destination, &filter, |contents| {
+            match contents.as_slice() {
+                b"s" => Ok(None),
+                b"f" => Err("failure".into()),
+                _ => Ok(Some(contents.repeat(2))),
+            }
+        });
+
+        assert_eq!(2, summary.processed);
+        assert_eq!(1, summary.skipped);
+        assert_eq!(1, summary.failed.len());
+        assert_eq!(source.join("nested/fail.map"), summary.failed[0].0);
+        assert_eq!(b"aa", &fs::read(destination.join("a.map")).unwrap()[..]);
+        assert_eq!(
+            b"bb",
+            &fs::read(destination.join("nested/b.map")).unwrap()[..]
+        );
+        assert!(!destination.join("nested/skip.map").exists());
+        assert!(!destination.join("excluded.txt").exists());
+    }
+
+    #[test]
+    fn test_decompress_tree() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        let source = root.join("source");
+        let destination = root.join("destination");
+        fs::create_dir_all(&source).unwrap();
+        let data: Vec<u8> = (0..500u32).map(|i| (i % 13) as u8).collect();
+        let compressed = compress(&data, &CompressOptions::default());
+        let mut corrupted = compressed.clone();
+        corrupted[50] ^= 0x01;
+        fs::write(source.join("valid.map"), &compressed).unwrap();
+        fs::write(source.join("corrupted.map"), &corrupted).unwrap();
+        fs::write(source.join("readme.txt"), b"plain text file").unwrap();
+
+        let filter = Filter::new(&[], &[]).unwrap();
+        let summary = process_tree(&source, &destination, &filter, decompress_game_file);
+
+        assert_eq!((1, 1), (summary.processed, summary.skipped));
+        assert_eq!(1, summary.failed.len());
+        assert_eq!(source.join("corrupted.map"), summary.failed[0].0);
+        assert_eq!(data, fs::read(destination.join("valid.map")).unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_process_tree_unreadable_entry() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        let source = root.join("source");
+        fs::create_dir_all(&source).unwrap();
+        fs::write(source.join("a.map"), b"a").unwrap();
+        std::os::unix::fs::symlink(root.join("missing"), source.join("broken.map")).unwrap();
+
+        let filter = Filter::new(&[], &[]).unwrap();
+        let summary = process_tree(&source, &root.join("destination"), &filter, |contents| {
+            Ok(Some(contents))
+        });
+
+        assert_eq!(1, summary.processed);
+        assert_eq!(1, summary.failed.len());
+        assert_eq!(source.join("broken.map"), summary.failed[0].0);
+    }
+}
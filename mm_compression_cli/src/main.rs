@@ -1,42 +1,415 @@
-use clap::{Parser, Subcommand};
-use mm_compression;
+mod batch;
+mod verify;
+
+use clap::{Args as ClapArgs, Parser, Subcommand, ValueEnum};
+use mm_compression::compression::{self, Token};
+use mm_compression::{
+    CompressOptions, CompressionLevel, CompressionType, DeobfuscatingReader, ObfuscatingWriter,
+    HEADER_SIZE,
+};
+use mm_file_formats::census::{self, Census, Counts};
+use std::error::Error;
 use std::fs;
-use std::io;
-use std::path::PathBuf;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
 
 #[derive(Parser, Debug)]
 #[command(
-    about = "A tools to manipulate compression and obfuscation formats of Magic & Mayhem files"
+    about = "A tools to manipulate compression and obfuscation formats of Magic & Mayhem files",
+    after_help = "Use - as source or destination for stdin or stdout, omitted destination is stdout. \
+                  Directory source is processed recursively into destination directory."
 )]
 struct Args {
     #[command(subcommand)]
     command: Commands,
 }
 
+/// Compression type stored in header
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Compression {
+    Lzss,
+    Uncompressed,
+}
+
+impl From<Compression> for mm_compression::Compression {
+    fn from(compression: Compression) -> Self {
+        match compression {
+            Compression::Lzss => mm_compression::Compression::LZSS,
+            Compression::Uncompressed => mm_compression::Compression::Uncompressed,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Level {
+    Fast,
+    Optimal,
+}
+
+impl From<Level> for CompressionLevel {
+    fn from(level: Level) -> Self {
+        match level {
+            Level::Fast => CompressionLevel::Fast,
+            Level::Optimal => CompressionLevel::Optimal,
+        }
+    }
+}
+
+/// Filters for directory source
+#[derive(ClapArgs, Debug)]
+struct FilterArgs {
+    /// Process only files matching glob, relative to source directory
+    #[arg(long)]
+    include: Vec<String>,
+    /// Don't process files matching glob
+    #[arg(long)]
+    exclude: Vec<String>,
+}
+
 #[derive(Subcommand, Debug)]
 enum Commands {
+    /// Deobfuscate and decompress game file, files of directory source
+    /// that aren't obfuscated are skipped
     Decompress {
         source: PathBuf,
         destination: Option<PathBuf>,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Compress and obfuscate file into format readable by the game
+    Compress {
+        source: PathBuf,
+        destination: Option<PathBuf>,
+        /// Seed of obfuscation stored at the start of file
+        #[arg(short, long, default_value_t = 0)]
+        seed: u32,
+        #[arg(short, long, value_enum, default_value_t = Compression::Lzss)]
+        compression: Compression,
+        /// LZSS compression level
+        #[arg(short, long, value_enum, default_value_t = Level::Fast)]
+        level: Level,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Obfuscate file as is, without adding header
+    Obfuscate {
+        source: PathBuf,
+        destination: Option<PathBuf>,
+        /// Seed of obfuscation stored at the start of file
+        #[arg(short, long, default_value_t = 0)]
+        seed: u32,
+    },
+    /// Deobfuscate file without decompressing, output starts with header
+    Deobfuscate {
+        source: PathBuf,
+        destination: Option<PathBuf>,
+    },
+    /// Check checksums of all obfuscated files of installation
+    Verify {
+        mm_path: PathBuf,
+        /// Print report as JSON
+        #[arg(long)]
+        json: bool,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Count files of installation by format, grouped by extension and
+    /// directory
+    Census { mm_path: PathBuf },
+    /// Print tokens of LZSS compressed file with their bit positions
+    Trace {
+        source: PathBuf,
+        /// Print only summary statistics
+        #[arg(short, long)]
+        summary: bool,
     },
 }
 
 fn main() {
-    let args = Args::parse();
+    if let Err(error) = run(Args::parse()) {
+        eprintln!("error: {}", error);
+        std::process::exit(1);
+    }
+}
 
+fn run(args: Args) -> Result<(), Box<dyn Error>> {
     match args.command {
         Commands::Decompress {
             source,
             destination,
+            filter,
+        } if source.is_dir() => {
+            process_tree(&source, destination, &filter, batch::decompress_game_file)?
+        }
+        Commands::Decompress {
+            source,
+            destination,
+            ..
+        } => {
+            let mut decoder = mm_compression::Decoder::new(open_source(&source)?)?;
+            let mut destination = create_destination(destination.as_deref())?;
+            io::copy(&mut decoder, &mut destination)?;
+            destination.flush()?;
+        }
+        Commands::Compress {
+            source,
+            destination,
+            seed,
+            compression,
+            level,
+            filter,
+        } => {
+            let options = CompressOptions {
+                seed,
+                compression: compression.into(),
+                level: level.into(),
+            };
+            if source.is_dir() {
+                return process_tree(&source, destination, &filter, |contents| {
+                    Ok(Some(mm_compression::compress(&contents, &options)))
+                });
+            }
+            let mut data = Vec::new();
+            open_source(&source)?.read_to_end(&mut data)?;
+            let mut destination = create_destination(destination.as_deref())?;
+            destination.write_all(&mm_compression::compress(&data, &options))?;
+            destination.flush()?;
+        }
+        Commands::Obfuscate {
+            source,
+            destination,
+            seed,
+        } => {
+            let destination = create_destination(destination.as_deref())?;
+            let mut writer = ObfuscatingWriter::new(destination, seed)?;
+            io::copy(&mut open_source(&source)?, &mut writer)?;
+            writer.finish()?;
+        }
+        Commands::Deobfuscate {
+            source,
+            destination,
+        } => {
+            let mut reader = DeobfuscatingReader::new(open_source(&source)?)?;
+            let mut destination = create_destination(destination.as_deref())?;
+            io::copy(&mut reader, &mut destination)?;
+            destination.flush()?;
+        }
+        Commands::Verify {
+            mm_path,
+            json,
+            filter,
         } => {
-            // TODO: use streaming
-            let mut destination_file: Box<dyn io::Write> = match destination {
-                Some(filename) => Box::new(fs::File::create(filename).unwrap()),
-                None => Box::new(io::stdout()),
+            let filter = batch::Filter::new(&filter.include, &filter.exclude)?;
+            let report = verify::verify(&mm_path, &filter);
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                println!("{}", report);
+            }
+            if !report.problems.is_empty() {
+                return Err(format!("{} files with problems", report.problems.len()).into());
+            }
+        }
+        Commands::Census { mm_path } => print_census(&census::census(mm_path)?)?,
+        Commands::Trace { source, summary } => trace(source, summary)?,
+    }
+    Ok(())
+}
+
+/// Processes directory tree, printing summary
+fn process_tree<F>(
+    source: &Path,
+    destination: Option<PathBuf>,
+    filter: &FilterArgs,
+    process: F,
+) -> Result<(), Box<dyn Error>>
+where
+    F: Fn(Vec<u8>) -> Result<Option<Vec<u8>>, batch::ProcessError> + Sync,
+{
+    let destination = destination.ok_or("destination directory is required")?;
+    let filter = batch::Filter::new(&filter.include, &filter.exclude)?;
+    let summary = batch::process_tree(source, &destination, &filter, process);
+    println!("{}", summary);
+    match summary.failed.len() {
+        0 => Ok(()),
+        failed => Err(format!("{} files failed", failed).into()),
+    }
+}
+
+fn is_stdio(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+fn open_source(path: &Path) -> io::Result<Box<dyn Read>> {
+    Ok(if is_stdio(path) {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(io::BufReader::new(fs::File::open(path)?))
+    })
+}
+
+fn create_destination(path: Option<&Path>) -> io::Result<Box<dyn Write>> {
+    Ok(match path {
+        Some(path) if !is_stdio(path) => Box::new(io::BufWriter::new(fs::File::create(path)?)),
+        _ => Box::new(io::BufWriter::new(io::stdout().lock())),
+    })
+}
+
+fn print_census(census: &Census) -> io::Result<()> {
+    fn write_table<'a, W: Write>(
+        output: &mut W,
+        title: &str,
+        rows: impl IntoIterator<Item = (String, &'a Counts)>,
+    ) -> io::Result<()> {
+        writeln!(
+            output,
+            "{:<32} {:>6} {:>7} {:>12} {:>6} {:>6} {:>19} {:>7}",
+            title,
+            "total",
+            "sprites",
+            "uncompressed",
+            "rle",
+            "lzss",
+            "unknown compression",
+            "unknown"
+        )?;
+        for (name, counts) in rows {
+            writeln!(
+                output,
+                "{:<32} {:>6} {:>7} {:>12} {:>6} {:>6} {:>19} {:>7}",
+                name,
+                counts.total(),
+                counts.sprites,
+                counts.uncompressed,
+                counts.rle,
+                counts.lzss,
+                counts.unknown_compression,
+                counts.unknown
+            )?;
+        }
+        writeln!(output)
+    }
+
+    let stdout = io::stdout();
+    let mut output = io::BufWriter::new(stdout.lock());
+    write_table(
+        &mut output,
+        "extension",
+        census
+            .by_extension
+            .iter()
+            .map(|(extension, counts)| (format!(".{}", extension), counts)),
+    )?;
+    write_table(
+        &mut output,
+        "directory",
+        census.by_directory.iter().map(|(directory, counts)| {
+            match directory.as_os_str().is_empty() {
+                true => (".".to_string(), counts),
+                false => (directory.display().to_string(), counts),
+            }
+        }),
+    )?;
+    write_table(&mut output, "", [("total".to_string(), &census.total)])?;
+    writeln!(output, "unknown files:")?;
+    for path in &census.unknown {
+        writeln!(output, "{}", path.display())?;
+    }
+    if !census.unreadable.is_empty() {
+        writeln!(output, "unreadable files:")?;
+        for path in &census.unreadable {
+            writeln!(output, "{}", path.display())?;
+        }
+    }
+    output.flush()
+}
+
+/// Counts of tokens in the stream
+#[derive(Default)]
+struct TraceStatistics {
+    literals: u64,
+    matches: u64,
+    match_bytes: u64,
+    /// Number of matches by length
+    match_lengths: [u64; 18],
+}
+
+impl TraceStatistics {
+    fn add(&mut self, token: Token) {
+        match token {
+            Token::Literal(_) => self.literals += 1,
+            Token::Match { length, .. } => {
+                self.matches += 1;
+                self.match_bytes += length as u64;
+                self.match_lengths[length] += 1;
+            }
+        }
+    }
+
+    fn write<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        let tokens = self.literals + self.matches;
+        let bytes = self.literals + self.match_bytes;
+        let percent = |part: u64, total: u64| match total {
+            0 => 0.0,
+            _ => part as f64 * 100.0 / total as f64,
+        };
+        writeln!(output, "tokens:   {}", tokens)?;
+        writeln!(
+            output,
+            "literals: {} ({:.1}% of tokens, {:.1}% of bytes)",
+            self.literals,
+            percent(self.literals, tokens),
+            percent(self.literals, bytes)
+        )?;
+        writeln!(
+            output,
+            "matches:  {} ({:.1}% of tokens, {:.1}% of bytes)",
+            self.matches,
+            percent(self.matches, tokens),
+            percent(self.match_bytes, bytes)
+        )?;
+        writeln!(output, "match lengths:")?;
+        let max_count = self.match_lengths.iter().copied().max().unwrap_or(0);
+        for (length, &count) in self.match_lengths.iter().enumerate().skip(2) {
+            let bar = match max_count {
+                0 => 0,
+                _ => (count * 40).div_ceil(max_count) as usize,
             };
+            let line = format!("{:>4} {:>8} {}", length, count, "#".repeat(bar));
+            writeln!(output, "{}", line.trim_end())?;
+        }
+        Ok(())
+    }
+}
 
-            let decompressed = mm_compression::read_decompressed(source).unwrap();
-            destination_file.write_all(&decompressed).unwrap();
+fn trace(source: PathBuf, summary: bool) -> Result<(), Box<dyn Error>> {
+    let info = mm_compression::inspect_file(&source)?;
+    if info.compression != CompressionType::LZSS {
+        return Err(format!("file is not LZSS compressed: {:?}", info.compression).into());
+    }
+    let mut reader = DeobfuscatingReader::new(io::BufReader::new(fs::File::open(&source)?))?;
+    reader.read_exact(&mut [0; HEADER_SIZE])?;
+
+    let stdout = io::stdout();
+    let mut output = io::BufWriter::new(stdout.lock());
+    let mut statistics = TraceStatistics::default();
+    for token in compression::decompress_sized(reader, info.unpacked_size as u64).tokens() {
+        let token = token?;
+        statistics.add(token.token);
+        if summary {
+            continue;
+        }
+        write!(
+            output,
+            "{:>10} {:>8} {}",
+            token.bit_offset, token.output_offset, token.token
+        )?;
+        if let Some(distance) = token.distance() {
+            write!(output, ", distance {}", distance)?;
         }
+        writeln!(output)?;
     }
+    statistics.write(&mut output)?;
+    output.flush()?;
+    Ok(())
 }
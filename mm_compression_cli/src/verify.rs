@@ -0,0 +1,227 @@
+//! Integrity verification of game installation
+
+use crate::batch::{self, Filter};
+use mm_compression::{check_integrity, CompressionType, Integrity};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    ChecksumMismatch,
+    Truncated,
+    Corrupt,
+    Unsupported,
+    Unreadable,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Status::ChecksumMismatch => "checksum mismatch",
+            Status::Truncated => "truncated",
+            Status::Corrupt => "corrupt",
+            Status::Unsupported => "unsupported compression",
+            Status::Unreadable => "unreadable",
+        })
+    }
+}
+
+/// Obfuscated file with problems
+#[derive(Serialize, Debug)]
+pub struct Problem {
+    /// Path relative to installation directory
+    pub path: String,
+    pub status: Status,
+    pub compression: Option<String>,
+    pub detail: Option<String>,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct Report {
+    pub files: usize,
+    pub not_obfuscated: usize,
+    pub valid: usize,
+    pub problems: Vec<Problem>,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for problem in &self.problems {
+            write!(f, "{}: {}", problem.path, problem.status)?;
+            if let Some(compression) = &problem.compression {
+                write!(f, " ({})", compression)?;
+            }
+            if let Some(detail) = &problem.detail {
+                write!(f, ": {}", detail)?;
+            }
+            writeln!(f)?;
+        }
+        write!(
+            f,
+            "{} files, {} not obfuscated, {} valid, {} with problems",
+            self.files,
+            self.not_obfuscated,
+            self.valid,
+            self.problems.len()
+        )
+    }
+}
+
+fn problem(
+    path: &Path,
+    status: Status,
+    compression: Option<CompressionType>,
+    detail: String,
+) -> Problem {
+    Problem {
+        path: path.to_string_lossy().into_owned(),
+        status,
+        compression: compression.map(|compression| format!("{:?}", compression)),
+        detail: Some(detail),
+    }
+}
+
+/// Checks every obfuscated file under `root` matching `filter`
+pub fn verify(root: &Path, filter: &Filter) -> Report {
+    let (files, errors) = batch::collect_files(root, filter);
+    let results: Vec<_> = files
+        .par_iter()
+        .map(|path| match fs::read(root.join(path)) {
+            Ok(contents) => Ok(check_integrity(&contents)),
+            Err(error) => Err(error),
+        })
+        .collect();
+
+    let mut report = Report {
+        files: files.len() + errors.len(),
+        ..Default::default()
+    };
+    for error in errors {
+        let path = error.path().unwrap_or(root);
+        let path = path.strip_prefix(root).unwrap_or(path);
+        let problem = problem(path, Status::Unreadable, None, error.to_string());
+        report.problems.push(problem);
+    }
+    for (path, result) in files.iter().zip(results) {
+        let integrity = match result {
+            Ok(integrity) => integrity,
+            Err(error) => {
+                let problem = problem(path, Status::Unreadable, None, error.to_string());
+                report.problems.push(problem);
+                continue;
+            }
+        };
+        report.problems.push(match integrity {
+            Integrity::NotObfuscated => {
+                report.not_obfuscated += 1;
+                continue;
+            }
+            Integrity::Valid(_) => {
+                report.valid += 1;
+                continue;
+            }
+            Integrity::ChecksumMismatch(compression, checksums) => {
+                let detail = checksums.verify().unwrap_err().to_string();
+                problem(path, Status::ChecksumMismatch, Some(compression), detail)
+            }
+            Integrity::Truncated(compression, error) => problem(
+                path,
+                Status::Truncated,
+                Some(compression),
+                error.to_string(),
+            ),
+            Integrity::Corrupt(compression, error) => {
+                problem(path, Status::Corrupt, Some(compression), error.to_string())
+            }
+            Integrity::Unsupported(compression) => Problem {
+                path: path.to_string_lossy().into_owned(),
+                status: Status::Unsupported,
+                compression: Some(format!("{:?}", compression)),
+                detail: None,
+            },
+        });
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mm_compression::{compress, obfuscation, CompressOptions, Compression};
+
+    /// Obfuscated file with valid checksums, marked as RLE compressed
+    fn rle_file(data: &[u8]) -> Vec<u8> {
+        let options = CompressOptions {
+            compression: Compression::Uncompressed,
+            ..Default::default()
+        };
+        let mut plain = obfuscation::deobfuscate(&compress(data, &options)).unwrap();
+        plain[0xc..0x10].copy_from_slice(&(CompressionType::RLE as u32).to_le_bytes());
+        obfuscation::obfuscate(&plain, 1)
+    }
+
+    #[test]
+    fn test_verify() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        fs::create_dir_all(root.join("Realms")).unwrap();
+        let data: Vec<u8> = (0..1000u32).map(|i| (i / 10) as u8).collect();
+        let compressed = compress(&data, &CompressOptions::default());
+        let mut corrupted = compressed.clone();
+        corrupted[100] ^= 0x01;
+        fs::write(root.join("Realms/valid.map"), &compressed).unwrap();
+        fs::write(root.join("Realms/corrupted.map"), &corrupted).unwrap();
+        fs::write(root.join("truncated.map"), &compressed[..30]).unwrap();
+        fs::write(root.join("rle.map"), rle_file(&data)).unwrap();
+        fs::write(root.join("readme.txt"), b"plain text file").unwrap();
+
+        let report = verify(root, &Filter::new(&[], &[]).unwrap());
+
+        let mismatch = "deobfuscation checksum does not match: \
+            expected 0x3f13f2a4, computed 0x15275d3d";
+        let truncated = "compressed stream ends early at bit 78 after 31 of 1000 bytes, \
+            last token: literal 0x2f";
+        assert_eq!(
+            serde_json::json!({
+                "files": 5,
+                "not_obfuscated": 1,
+                "valid": 1,
+                "problems": [
+                    {
+                        "path": "Realms/corrupted.map",
+                        "status": "checksum_mismatch",
+                        "compression": "LZSS",
+                        "detail": mismatch,
+                    },
+                    {
+                        "path": "rle.map",
+                        "status": "unsupported",
+                        "compression": "RLE",
+                        "detail": null,
+                    },
+                    {
+                        "path": "truncated.map",
+                        "status": "truncated",
+                        "compression": "LZSS",
+                        "detail": truncated,
+                    },
+                ],
+            }),
+            serde_json::to_value(&report).unwrap()
+        );
+        assert_eq!(
+            format!(
+                "Realms/corrupted.map: checksum mismatch (LZSS): {}\n\
+                 rle.map: unsupported compression (RLE)\n\
+                 truncated.map: truncated (LZSS): {}\n\
+                 5 files, 1 not obfuscated, 1 valid, 3 with problems",
+                mismatch, truncated
+            ),
+            report.to_string()
+        );
+    }
+}
@@ -1,17 +1,18 @@
 use base64::Engine;
+use image::RgbaImage;
 use mm_file_formats::sprites::{Frame, Sprites};
 use std::env;
-use std::fs::File;
+use std::fs;
 use std::io::{self, stdout, Cursor, Write};
+use std::process;
 
-fn write_frame_img<W: Write>(out: &mut W, frame: &Frame) -> io::Result<()> {
-    if frame.image.width() == 0 || frame.image.height() == 0 {
+fn write_frame_img<W: Write>(out: &mut W, frame: &Frame, image: &RgbaImage) -> io::Result<()> {
+    if frame.width == 0 || frame.height == 0 {
         return Ok(());
     }
 
     let mut png: Cursor<Vec<u8>> = Cursor::new(Vec::with_capacity(1024 * 64));
-    frame
-        .image
+    image
         .write_to(&mut png, image::ImageOutputFormat::Png)
         .expect("Write png file");
     let base64_png = base64::engine::general_purpose::STANDARD_NO_PAD.encode(png.get_ref());
@@ -26,10 +27,18 @@ fn write_frame_img<W: Write>(out: &mut W, frame: &Frame) -> io::Result<()> {
     Ok(())
 }
 
-fn write_frame<W: Write>(out: &mut W, frame: &Frame, index: i32) -> io::Result<()> {
+fn write_frame<W: Write>(
+    out: &mut W,
+    sprites: &Sprites,
+    frame: &Frame,
+    index: usize,
+) -> io::Result<()> {
     let name = &frame.name;
     writeln!(out, "<li>")?;
-    write_frame_img(out, frame)?;
+    let image = sprites
+        .to_rgba(frame)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+    write_frame_img(out, frame, &image)?;
     writeln!(out, "<div class=\"sprite-number\">{index}</div>")?;
     writeln!(out, "<div class=\"sprite-name\">{name}</div>")?;
     writeln!(out, "</li>")?;
@@ -38,7 +47,14 @@ fn write_frame<W: Write>(out: &mut W, frame: &Frame, index: i32) -> io::Result<(
 
 fn main() -> io::Result<()> {
     let filename = env::args().nth(1).expect("input file argument required");
-    let sprites = Sprites::parse(File::open(filename).expect("open sprite file"));
+    let contents = fs::read(filename).expect("read sprite file");
+    let sprites = match Sprites::from_bytes(&contents) {
+        Ok(sprites) => sprites,
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    };
 
     let mut out = stdout();
 
@@ -51,10 +67,8 @@ fn main() -> io::Result<()> {
 
     writeln!(&out, "<ul class=\"sprites\">")?;
 
-    let mut i = 0;
-    for frame in sprites.frames {
-        write_frame(&mut out, &frame, i)?;
-        i += 1;
+    for (i, frame) in sprites.frames.iter().enumerate() {
+        write_frame(&mut out, &sprites, frame, i)?;
     }
 
     writeln!(&out, "</ul>")?;
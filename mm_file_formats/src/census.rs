@@ -0,0 +1,179 @@
+//! Classification of files of game installation by format
+
+use crate::sprites;
+use mm_compression::CompressionType;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileClass {
+    /// Not obfuscated sprite sheet
+    Sprites,
+    /// Obfuscated file with matching checksum
+    Obfuscated(CompressionType),
+    Unknown,
+}
+
+/// Classifies file by its `contents`
+pub fn classify(contents: &[u8]) -> FileClass {
+    if contents.starts_with(sprites::MAGIC) {
+        return FileClass::Sprites;
+    }
+    match mm_compression::inspect(contents) {
+        Ok(info) if info.deobfuscation_checksum_matches => FileClass::Obfuscated(info.compression),
+        _ => FileClass::Unknown,
+    }
+}
+
+/// Number of files of each class
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Counts {
+    pub sprites: usize,
+    pub uncompressed: usize,
+    pub rle: usize,
+    pub lzss: usize,
+    /// Obfuscated, but with unknown compression type
+    pub unknown_compression: usize,
+    pub unknown: usize,
+}
+
+impl Counts {
+    fn add(&mut self, class: FileClass) {
+        *match class {
+            FileClass::Sprites => &mut self.sprites,
+            FileClass::Obfuscated(CompressionType::Uncompressed) => &mut self.uncompressed,
+            FileClass::Obfuscated(CompressionType::RLE) => &mut self.rle,
+            FileClass::Obfuscated(CompressionType::LZSS) => &mut self.lzss,
+            FileClass::Obfuscated(CompressionType::Unknown) => &mut self.unknown_compression,
+            FileClass::Unknown => &mut self.unknown,
+        } += 1;
+    }
+
+    pub fn total(&self) -> usize {
+        self.sprites
+            + self.uncompressed
+            + self.rle
+            + self.lzss
+            + self.unknown_compression
+            + self.unknown
+    }
+}
+
+/// Classes of files of directory tree
+#[derive(Debug, Default)]
+pub struct Census {
+    pub total: Counts,
+    /// Keyed by lowercase extension, empty for files without one
+    pub by_extension: BTreeMap<String, Counts>,
+    /// Keyed by directory relative to root
+    pub by_directory: BTreeMap<PathBuf, Counts>,
+    /// Paths relative to root of files of [FileClass::Unknown]
+    pub unknown: Vec<PathBuf>,
+    /// Paths relative to root of entries that couldn't be read, not
+    /// included in counts
+    pub unreadable: Vec<PathBuf>,
+}
+
+impl Census {
+    fn add(&mut self, path: &Path, class: FileClass) {
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let directory = path.parent().unwrap_or(Path::new("")).to_path_buf();
+
+        self.total.add(class);
+        self.by_extension.entry(extension).or_default().add(class);
+        self.by_directory.entry(directory).or_default().add(class);
+        if class == FileClass::Unknown {
+            self.unknown.push(path.to_path_buf());
+        }
+    }
+}
+
+/// Classifies every file under `root`, e.g. game installation
+/// directory
+///
+/// Fails only if `root` itself can't be read, other entries that can't
+/// be read are listed in [Census::unreadable].
+pub fn census<P: AsRef<Path>>(root: P) -> io::Result<Census> {
+    let root = root.as_ref();
+    let mut census = Census::default();
+    for entry in WalkDir::new(root).follow_links(true).sort_by_file_name() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(error) if error.depth() == 0 => return Err(error.into()),
+            Err(error) => {
+                let path = error.path().unwrap_or(root);
+                census
+                    .unreadable
+                    .push(path.strip_prefix(root).unwrap_or(path).to_path_buf());
+                continue;
+            }
+        };
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative = entry.path().strip_prefix(root).unwrap();
+        match fs::read(entry.path()) {
+            Ok(contents) => census.add(relative, classify(&contents)),
+            Err(_) => census.unreadable.push(relative.to_path_buf()),
+        }
+    }
+    census.unknown.sort();
+    census.unreadable.sort();
+    Ok(census)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mm_compression::{compress, CompressOptions, Compression};
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(FileClass::Sprites, classify(b"SPR\0\x01\x00\x00\x00"));
+        assert_eq!(FileClass::Unknown, classify(b"some text"));
+        assert_eq!(FileClass::Unknown, classify(&[]));
+        for compression in [Compression::Uncompressed, Compression::LZSS] {
+            let options = CompressOptions {
+                compression,
+                ..Default::default()
+            };
+            assert_eq!(
+                FileClass::Obfuscated(compression.into()),
+                classify(&compress(&[1; 100], &options))
+            );
+        }
+    }
+
+    #[test]
+    fn test_census() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        fs::create_dir_all(root.join("Realms/Celtic")).unwrap();
+        let lzss = compress(&[1; 100], &CompressOptions::default());
+        fs::write(root.join("Realms/Celtic/CFsec1.map"), &lzss).unwrap();
+        fs::write(root.join("Realms/Celtic/CFsec2.MAP"), &lzss).unwrap();
+        fs::write(root.join("Realms/Celtic/Terrain.spr"), b"SPR\0").unwrap();
+        fs::write(root.join("readme"), b"text").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(root.join("missing"), root.join("broken.map")).unwrap();
+
+        assert!(census(root.join("missing")).is_err());
+        let census = census(root).unwrap();
+
+        assert_eq!(4, census.total.total());
+        assert_eq!(2, census.by_extension["map"].lzss);
+        assert_eq!(1, census.by_extension["spr"].sprites);
+        assert_eq!(1, census.by_extension[""].unknown);
+        let celtic = &census.by_directory[Path::new("Realms/Celtic")];
+        assert_eq!((2, 1), (celtic.lzss, celtic.sprites));
+        assert_eq!(vec![PathBuf::from("readme")], census.unknown);
+        #[cfg(unix)]
+        assert_eq!(vec![PathBuf::from("broken.map")], census.unreadable);
+    }
+}
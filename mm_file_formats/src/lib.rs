@@ -1,2 +1,3 @@
+pub mod census;
 pub mod map_section;
 pub mod sprites;
@@ -1,5 +1,7 @@
+use mm_compression::DecompressError;
 use nom::{combinator::verify, number::complete::le_u32, sequence::tuple, Finish};
 use std::convert::TryInto;
+use std::path::Path;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -8,6 +10,8 @@ pub enum MapSectionError {
     PrematureEndWhenSeekingTile { x: u32, y: u32, z: u32 },
     #[error("parse error: {0}")]
     ParseError(String),
+    #[error("can't decode map section file: {0}")]
+    DecodeError(#[from] DecompressError),
 }
 
 impl<'a> From<nom::error::Error<&'a [u8]>> for MapSectionError {
@@ -35,7 +39,8 @@ const TILES_OFFSET: usize = 0x4c;
 impl MapSection {
     pub fn from_contents(contents: Vec<u8>) -> Result<Self> {
         let result: std::result::Result<_, nom::error::Error<_>> =
-            tuple((verify(le_u32, |v| *v == 6), le_u32, le_u32, le_u32))(&*contents).finish();
+            tuple((verify(le_u32, |v| *v == 6), le_u32, le_u32, le_u32))(contents.as_slice())
+                .finish();
         let (_, (_, size_x, size_y, size_z)) = result?;
 
         let map_section = MapSection {
@@ -50,6 +55,12 @@ impl MapSection {
         Ok(map_section)
     }
 
+    /// Reads map section file, either obfuscated as in the game or
+    /// already decompressed
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_contents(mm_compression::open_game_file(path)?.contents)
+    }
+
     pub fn tile_at(&self, x: u32, y: u32, z: u32) -> Tile {
         // PrematureEndWhenSeekingTile shouldn't happen as it's
         // checked in constructor
@@ -1,8 +1,10 @@
 use image::Pixel;
 use image::{ImageBuffer, Rgb, Rgba, RgbaImage};
+use mm_compression::DecompressError;
 use nom::{
     bytes::complete::{tag, take},
     combinator::{map, map_res},
+    error::ErrorKind,
     multi::count,
     number::complete::{le_i32, le_u32, le_u8},
     sequence::tuple,
@@ -10,25 +12,35 @@ use nom::{
 };
 use std::fmt;
 use std::fs::File;
+use std::io;
 use std::io::prelude::*;
-use std::iter;
 use std::str::from_utf8;
+use std::sync::OnceLock;
+use thiserror::Error;
+
+/// Magic value at the start of sprite files
+pub const MAGIC: &[u8] = mm_compression::SPRITES_MAGIC;
 
 type Rgb8 = Rgb<u8>;
-type Rgba8 = Rgba<u8>;
 type Palette = Vec<Rgb8>;
 
 #[derive(Debug)]
 pub struct Sprites {
+    /// Unknown values of file header, written back as is
+    pub unknown: [u32; 3],
     pub palettes: Vec<Palette>,
     pub frames: Vec<Frame>,
 }
 
 struct SpriteFileHeader {
+    unknown: [u32; 3],
     palettes: Vec<Palette>,
-    frame_offsets: Vec<u32>,
+    /// Relative to the end of header as parsed, absolute after
+    /// [parse_header]
+    frame_offsets: Vec<usize>,
 }
 
+#[derive(Clone, PartialEq, Eq)]
 pub struct Frame {
     pub width: u32,
     pub height: u32,
@@ -37,7 +49,38 @@ pub struct Frame {
     pub unknown1: u32,
     pub unknown2: u32,
     pub name: String,
-    pub image: ImageBuffer<Rgba8, Vec<u8>>,
+    /// Index into [Sprites::palettes] of palette the frame uses
+    pub palette_index: u32,
+    /// Palette index of each pixel row by row, 0 for transparent ones
+    pub indices: Vec<u8>,
+    /// Whether each pixel is opaque, row by row
+    pub opaque: Vec<bool>,
+}
+
+impl Frame {
+    /// Palette index of pixel at `x`, `y`, `None` if it's transparent
+    pub fn index_at(&self, x: u32, y: u32) -> Option<u8> {
+        let i = (y * self.width + x) as usize;
+        self.opaque[i].then_some(self.indices[i])
+    }
+
+    /// Converts frame to RGBA using `palette`, usually the one at
+    /// [Frame::palette_index], but any other works for palette swaps
+    ///
+    /// Panics if `palette` has less than 256 colours.
+    pub fn to_rgba(&self, palette: &[Rgb8]) -> RgbaImage {
+        let transparent = Rgba([0, 0, 0, 0]);
+        let pixels = self.indices.iter().zip(&self.opaque);
+        let mut image = ImageBuffer::new(self.width, self.height);
+        for (output_pixel, (&index, &opaque)) in image.pixels_mut().zip(pixels) {
+            if opaque {
+                *output_pixel = palette[index as usize].to_rgba();
+            } else {
+                *output_pixel = transparent;
+            }
+        }
+        image
+    }
 }
 
 impl fmt::Debug for Frame {
@@ -50,53 +93,403 @@ impl fmt::Debug for Frame {
     }
 }
 
+/// Why sprite file couldn't be parsed
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum Reason {
+    #[error("not a sprite file")]
+    BadMagic,
+    #[error("premature end of file")]
+    PrematureEnd,
+    #[error("name is not valid UTF-8")]
+    InvalidName,
+    #[error("palette {index} doesn't exist, there are {count} palettes")]
+    MissingPalette { index: u32, count: usize },
+    #[error("{width}x{height} frame exceeds limit of {MAX_FRAME_PIXELS} pixels")]
+    TooLarge { width: u32, height: u32 },
+    #[error("row {row} starts past end of file")]
+    RowOutOfBounds { row: u32 },
+    #[error("runs of row {row} continue past end of file")]
+    RunsPastEnd { row: u32 },
+    #[error("pixels of row {row} continue past end of file")]
+    PixelsPastEnd { row: u32 },
+}
+
+/// Offsets are in decoded file contents, where the problem was found
+#[derive(Error, Debug)]
+pub enum SpriteError {
+    #[error("can't read sprite file: {0}")]
+    ReadError(#[from] std::io::Error),
+    #[error("can't decode sprite file: {0}")]
+    DecodeError(#[from] DecompressError),
+    #[error("invalid header at offset {offset:#x}: {reason}")]
+    InvalidHeader { offset: usize, reason: Reason },
+    #[error("invalid frame {index} at offset {offset:#x}: {reason}")]
+    InvalidFrame {
+        index: usize,
+        offset: usize,
+        reason: Reason,
+    },
+    #[error("frame {index} doesn't exist, there are {count} frames")]
+    MissingFrame { index: usize, count: usize },
+    #[error("frame uses palette {index}, but there are {count} palettes")]
+    MissingPalette { index: u32, count: usize },
+}
+
+/// Limit of frame width multiplied by height, sprites of the game are
+/// much smaller
+pub const MAX_FRAME_PIXELS: u64 = 4096 * 4096;
+
+type Failure = (usize, Reason);
+
 impl Sprites {
-    pub fn parse(mut file: File) -> Sprites {
-        let mut buf: Vec<u8> = Vec::new();
-        file.read_to_end(&mut buf).expect("Can't read sprite file");
+    /// Same as [Sprites::from_reader]
+    pub fn parse(file: File) -> Result<Sprites, SpriteError> {
+        Self::from_reader(file)
+    }
+
+    /// Parses contents of sprite file, either obfuscated or not
+    pub fn from_bytes(bytes: &[u8]) -> Result<Sprites, SpriteError> {
+        LazySprites::from_bytes(bytes)?.into_sprites()
+    }
+
+    /// Reads sprite file to the end and parses it, see
+    /// [Sprites::from_bytes]
+    pub fn from_reader(reader: impl Read) -> Result<Sprites, SpriteError> {
+        LazySprites::from_reader(reader)?.into_sprites()
+    }
+
+    /// Palette at [Frame::palette_index] of `frame`
+    pub fn palette(&self, frame: &Frame) -> Result<&[Rgb8], SpriteError> {
+        frame_palette(&self.palettes, frame)
+    }
 
-        let (payload, header) = header(&buf[..]).expect("Can't parse header");
+    /// Converts `frame` to RGBA using its own palette
+    pub fn to_rgba(&self, frame: &Frame) -> Result<RgbaImage, SpriteError> {
+        Ok(frame.to_rgba(self.palette(frame)?))
+    }
 
-        let frames = header
-            .frame_offsets
+    /// Writes sprite file in format read by the game, not obfuscated
+    ///
+    /// Fails with [io::ErrorKind::InvalidInput] if palette doesn't have
+    /// 256 colours or frame can't be read back: name longer than 8
+    /// bytes, missing palette, pixels not matching its size or more
+    /// than [MAX_FRAME_PIXELS] of them.
+    pub fn write(&self, mut writer: impl Write) -> io::Result<()> {
+        if self.palettes.iter().any(|palette| palette.len() != 256) {
+            return Err(invalid_input("palette must have 256 colours".to_string()));
+        }
+        let frames = self
+            .frames
             .iter()
-            .map(|&offset| {
-                frame(&header.palettes)(&payload[offset as usize..])
-                    .unwrap_or_else(|_| panic!("Can't decode frame at {}", offset))
-                    .1
-            })
-            .collect();
+            .map(|frame| self.frame_bytes(frame))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let [unknown1, unknown2, unknown3] = self.unknown;
+        let mut header = MAGIC.to_vec();
+        for value in [
+            unknown1,
+            unknown2,
+            frames.len() as u32,
+            self.palettes.len() as u32,
+            unknown3,
+        ] {
+            header.extend(value.to_le_bytes());
+        }
+        for color in self.palettes.iter().flatten() {
+            header.extend(color.0);
+        }
+        let mut offset = 0;
+        for frame in &frames {
+            header.extend((offset as u32).to_le_bytes());
+            offset += frame.len();
+        }
+
+        writer.write_all(&header)?;
+        for frame in &frames {
+            writer.write_all(frame)?;
+        }
+        Ok(())
+    }
+
+    /// Encodes `frame` with offsets of rows relative to its start
+    fn frame_bytes(&self, frame: &Frame) -> io::Result<Vec<u8>> {
+        let invalid = |problem| invalid_input(format!("frame '{}' {}", frame.name, problem));
+        if frame.width as u64 * frame.height as u64 > MAX_FRAME_PIXELS {
+            return Err(invalid("is too large"));
+        }
+        let width = frame.width as usize;
+        let size = width * frame.height as usize;
+        if frame.indices.len() != size || frame.opaque.len() != size {
+            return Err(invalid("has pixels not matching its size"));
+        }
+        if frame.palette_index as usize >= self.palettes.len() {
+            return Err(invalid("uses missing palette"));
+        }
+        let mut name = frame.name.as_bytes().to_vec();
+        if name.len() > 8 {
+            return Err(invalid("has name longer than 8 bytes"));
+        }
+        name.resize(8, 0);
+
+        let mut runs = Vec::new();
+        let mut pixels = Vec::new();
+        let mut row_offsets = Vec::new();
+        // Rows of zero-width frames have no runs, but still offsets
+        for row in 0..frame.height as usize {
+            let pixels_range = row * width..(row + 1) * width;
+            row_offsets.push((runs.len(), pixels.len()));
+            encode_row(
+                &frame.indices[pixels_range.clone()],
+                &frame.opaque[pixels_range],
+                &mut runs,
+                &mut pixels,
+            );
+        }
+
+        let rows_start = FRAME_HEADER_SIZE + 8 * row_offsets.len();
+        let pixels_start = rows_start + runs.len();
+        let frame_size = pixels_start + pixels.len();
+        let mut output = Vec::with_capacity(frame_size);
+        for value in [frame_size as u32, frame.width, frame.height] {
+            output.extend(value.to_le_bytes());
+        }
+        for value in [frame.center_x, frame.center_y] {
+            output.extend(value.to_le_bytes());
+        }
+        output.extend(name);
+        for value in [frame.palette_index, frame.unknown1, frame.unknown2] {
+            output.extend(value.to_le_bytes());
+        }
+        for (runs_offset, pixels_offset) in row_offsets {
+            output.extend(((rows_start + runs_offset) as u32).to_le_bytes());
+            output.extend(((pixels_start + pixels_offset) as u32).to_le_bytes());
+        }
+        output.extend(runs);
+        output.extend(pixels);
+        Ok(output)
+    }
+}
+
+/// Size of frame fields before offsets of rows
+const FRAME_HEADER_SIZE: usize = 5 * 4 + 8 + 3 * 4;
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// Appends alternating skip and opaque runs of row, as read by
+/// [IterPixelRow], and indices of its opaque pixels
+fn encode_row(indices: &[u8], opaque: &[bool], runs: &mut Vec<u8>, pixels: &mut Vec<u8>) {
+    let mut x = 0;
+    while x < opaque.len() {
+        let skip = opaque[x..]
+            .iter()
+            .take(u8::MAX as usize)
+            .take_while(|&&opaque| !opaque)
+            .count();
+        x += skip;
+        let run = opaque[x..]
+            .iter()
+            .take(u8::MAX as usize)
+            .take_while(|&&opaque| opaque)
+            .count();
+        pixels.extend(&indices[x..x + run]);
+        x += run;
+        runs.extend([skip as u8, run as u8]);
+    }
+}
+
+/// Sprite sheet which decodes frames the first time they're requested
+///
+/// Keeps decoded file contents, only header is parsed upfront. Frames
+/// are cached once decoded, errors are not.
+#[derive(Debug)]
+pub struct LazySprites {
+    /// See [Sprites::unknown]
+    pub unknown: [u32; 3],
+    pub palettes: Vec<Palette>,
+    contents: Vec<u8>,
+    frame_offsets: Vec<usize>,
+    frames: Vec<OnceLock<Frame>>,
+}
+
+impl LazySprites {
+    /// Parses header of sprite file contents, either obfuscated or not
+    pub fn from_bytes(bytes: &[u8]) -> Result<LazySprites, SpriteError> {
+        Self::from_contents(decode_contents(bytes.to_vec())?)
+    }
+
+    /// Reads sprite file to the end and parses its header
+    pub fn from_reader(reader: impl Read) -> Result<LazySprites, SpriteError> {
+        Self::from_contents(read_contents(reader)?)
+    }
 
-        Sprites {
+    fn from_contents(contents: Vec<u8>) -> Result<LazySprites, SpriteError> {
+        let header = parse_header(&contents)?;
+        Ok(LazySprites {
+            unknown: header.unknown,
             palettes: header.palettes,
-            frames,
+            frames: header
+                .frame_offsets
+                .iter()
+                .map(|_| OnceLock::new())
+                .collect(),
+            frame_offsets: header.frame_offsets,
+            contents,
+        })
+    }
+
+    /// Palette at [Frame::palette_index] of `frame`
+    pub fn palette(&self, frame: &Frame) -> Result<&[Rgb8], SpriteError> {
+        frame_palette(&self.palettes, frame)
+    }
+
+    /// Number of frames
+    pub fn len(&self) -> usize {
+        self.frame_offsets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frame_offsets.is_empty()
+    }
+
+    /// Frame at `index`, decoded on the first call
+    pub fn frame(&self, index: usize) -> Result<&Frame, SpriteError> {
+        let cached = self.frames.get(index).ok_or(SpriteError::MissingFrame {
+            index,
+            count: self.len(),
+        })?;
+        if let Some(frame) = cached.get() {
+            return Ok(frame);
         }
+        let offset = self.frame_offsets[index];
+        let frame = decode_frame(&self.contents, index, offset, &self.palettes)?;
+        // Another thread may have decoded it in the meantime
+        Ok(cached.get_or_init(|| frame))
+    }
+
+    /// Decodes frames which are not decoded yet
+    pub fn into_sprites(self) -> Result<Sprites, SpriteError> {
+        let LazySprites {
+            unknown,
+            palettes,
+            contents,
+            frame_offsets,
+            frames,
+        } = self;
+        let frames = frames
+            .into_iter()
+            .zip(frame_offsets)
+            .enumerate()
+            .map(|(index, (cached, offset))| match cached.into_inner() {
+                Some(frame) => Ok(frame),
+                None => decode_frame(&contents, index, offset, &palettes),
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Sprites {
+            unknown,
+            palettes,
+            frames,
+        })
     }
 }
 
+fn frame_palette<'a>(palettes: &'a [Palette], frame: &Frame) -> Result<&'a [Rgb8], SpriteError> {
+    palettes
+        .get(frame.palette_index as usize)
+        .map(|palette| &palette[..])
+        .ok_or(SpriteError::MissingPalette {
+            index: frame.palette_index,
+            count: palettes.len(),
+        })
+}
+
+/// Deobfuscates sprite sheets which come obfuscated, e.g. from mods
+fn decode_contents(contents: Vec<u8>) -> Result<Vec<u8>, SpriteError> {
+    Ok(mm_compression::decode_game_file(contents)?.contents)
+}
+
+fn read_contents(mut reader: impl Read) -> Result<Vec<u8>, SpriteError> {
+    let mut buf: Vec<u8> = Vec::new();
+    reader.read_to_end(&mut buf)?;
+    decode_contents(buf)
+}
+
+/// Header with absolute offsets of frames in decoded contents
+fn parse_header(buf: &[u8]) -> Result<SpriteFileHeader, SpriteError> {
+    let (payload, mut header) = header(buf)
+        .map_err(|error| parse_failure(buf, error))
+        .map_err(|(offset, reason)| SpriteError::InvalidHeader { offset, reason })?;
+    let payload_offset = buf.len() - payload.len();
+    for offset in &mut header.frame_offsets {
+        *offset += payload_offset;
+    }
+    Ok(header)
+}
+
+fn decode_frame(
+    buf: &[u8],
+    index: usize,
+    offset: usize,
+    palettes: &[Palette],
+) -> Result<Frame, SpriteError> {
+    frame(buf, offset, palettes).map_err(|(offset, reason)| SpriteError::InvalidFrame {
+        index,
+        offset,
+        reason,
+    })
+}
+
+/// Offset in `file` where nom parser failed and why
+fn parse_failure(file: &[u8], error: nom::Err<nom::error::Error<&[u8]>>) -> Failure {
+    match error {
+        nom::Err::Error(error) | nom::Err::Failure(error) => (
+            file.len() - error.input.len(),
+            match error.code {
+                ErrorKind::Tag => Reason::BadMagic,
+                ErrorKind::MapRes => Reason::InvalidName,
+                _ => Reason::PrematureEnd,
+            },
+        ),
+        nom::Err::Incomplete(_) => (file.len(), Reason::PrematureEnd),
+    }
+}
+
+/// Why row couldn't be decoded, see [Reason]
+enum RowError {
+    RunsPastEnd,
+    PixelsPastEnd,
+}
+
 struct IterPixelRow<'a> {
     runs: &'a [u8],
     pixels: &'a [u8],
     is_skip: bool,
     pixels_left: u8,
-    palette: &'a [Rgb8],
 }
 
 impl<'a> Iterator for IterPixelRow<'a> {
-    type Item = Rgba8;
-    fn next(&mut self) -> Option<Rgba8> {
+    /// Palette index, `None` for skipped pixel
+    type Item = Result<Option<u8>, RowError>;
+    fn next(&mut self) -> Option<Self::Item> {
         while self.pixels_left == 0 {
             self.is_skip = !self.is_skip;
-            self.pixels_left = self.runs[0];
-            self.runs = &self.runs[1..];
+            let Some((&run, runs)) = self.runs.split_first() else {
+                return Some(Err(RowError::RunsPastEnd));
+            };
+            self.pixels_left = run;
+            self.runs = runs;
         }
         self.pixels_left -= 1;
         if self.is_skip {
-            Some(Rgba([0, 0, 0, 0]))
+            Some(Ok(None))
         } else {
-            let pixel = self.pixels[0];
-            self.pixels = &self.pixels[1..];
-            Some(self.palette[pixel as usize].to_rgba())
+            let Some((&pixel, pixels)) = self.pixels.split_first() else {
+                return Some(Err(RowError::PixelsPastEnd));
+            };
+            self.pixels = pixels;
+            Some(Ok(Some(pixel)))
         }
     }
 }
@@ -106,72 +499,119 @@ struct LineOffsets {
     pixels_offset: u32,
 }
 
+/// Palette indices and opacity of pixels, row by row
+struct IndexedPixels {
+    indices: Vec<u8>,
+    opaque: Vec<bool>,
+}
+
+/// Decodes rows of frame starting at `frame_offset` in `file`, row
+/// offsets are relative to frame start
 fn pixels(
-    input: &[u8],
+    file: &[u8],
+    frame_offset: usize,
     lines: impl IntoIterator<Item = LineOffsets>,
     width: u32,
     height: u32,
-    palette: &[Rgb8],
-) -> RgbaImage {
-    let mut image = ImageBuffer::new(width, height);
-
-    // rows_mut crashes on zero-width images:
-    // https://github.com/image-rs/image/issues/994
-    if width == 0 || height == 0 {
-        return image;
+) -> Result<IndexedPixels, Failure> {
+    let size = width as usize * height as usize;
+    let mut output = IndexedPixels {
+        indices: vec![0; size],
+        opaque: vec![false; size],
+    };
+    if size == 0 {
+        return Ok(output);
     }
 
-    for (offsets, output_row) in lines.into_iter().zip(image.rows_mut()) {
+    let output_rows = output
+        .indices
+        .chunks_mut(width as usize)
+        .zip(output.opaque.chunks_mut(width as usize));
+    for (row, (offsets, (output_indices, output_opaque))) in
+        (0..).zip(lines.into_iter().zip(output_rows))
+    {
+        let runs_offset = frame_offset + offsets.runs_offset as usize;
+        let pixels_offset = frame_offset + offsets.pixels_offset as usize;
+        let (Some(runs), Some(pixels)) = (file.get(runs_offset..), file.get(pixels_offset..))
+        else {
+            return Err((
+                runs_offset.max(pixels_offset),
+                Reason::RowOutOfBounds { row },
+            ));
+        };
         let input_pixels = IterPixelRow {
-            runs: &input[offsets.runs_offset as usize..],
-            pixels: &input[offsets.pixels_offset as usize..],
+            runs,
+            pixels,
             is_skip: false,
             pixels_left: 0,
-            palette,
-        }
-        .chain(iter::repeat(Rgba([0, 0, 0, 0])))
-        .take(width as usize);
+        };
 
-        for (input_pixel, output_pixel) in input_pixels.zip(output_row) {
-            *output_pixel = input_pixel;
+        for (input_pixel, (index, opaque)) in
+            input_pixels.zip(output_indices.iter_mut().zip(output_opaque))
+        {
+            let input_pixel = input_pixel.map_err(|error| match error {
+                RowError::RunsPastEnd => (file.len(), Reason::RunsPastEnd { row }),
+                RowError::PixelsPastEnd => (file.len(), Reason::PixelsPastEnd { row }),
+            })?;
+            if let Some(input_index) = input_pixel {
+                *index = input_index;
+                *opaque = true;
+            }
         }
     }
 
-    image
+    Ok(output)
 }
 
-fn frame(palettes: &[Palette]) -> impl Fn(&[u8]) -> IResult<&[u8], Frame> + '_ {
-    move |i: &[u8]| {
-        let (input, (_size, width, height, center_x, center_y)) =
-            tuple((le_u32, le_u32, le_u32, le_i32, le_i32))(i)?;
-        let (input, name) = map(map_res(take(8usize), from_utf8), String::from)(input)?;
-        let (input, palette_index) = le_u32(input)?;
-        let (input, (unknown1, unknown2)) = tuple((le_u32, le_u32))(input)?;
-        let (input, rows) = count(
-            map(tuple((le_u32, le_u32)), |(runs_offset, pixels_offset)| {
-                LineOffsets {
-                    runs_offset,
-                    pixels_offset,
-                }
-            }),
-            height as usize,
-        )(input)?;
-        let image = pixels(i, rows, width, height, &palettes[palette_index as usize]);
+/// Parses frame starting at `offset` in `file`
+fn frame(file: &[u8], offset: usize, palettes: &[Palette]) -> Result<Frame, Failure> {
+    let i = file.get(offset..).ok_or((offset, Reason::PrematureEnd))?;
+    let failure = |error| parse_failure(file, error);
 
-        Ok((
-            input,
-            Frame {
-                width,
-                height,
-                center_x,
-                center_y,
-                unknown1,
-                unknown2,
-                name,
-                image,
+    let (input, (_size, width, height, center_x, center_y)) =
+        tuple((le_u32, le_u32, le_u32, le_i32, le_i32))(i).map_err(failure)?;
+    let (input, name) =
+        map(map_res(take(8usize), from_utf8), String::from)(input).map_err(failure)?;
+    let palette_offset = file.len() - input.len();
+    let (input, palette_index) = le_u32(input).map_err(failure)?;
+    let (input, (unknown1, unknown2)) = tuple((le_u32, le_u32))(input).map_err(failure)?;
+    let (_, rows) = count(
+        map(tuple((le_u32, le_u32)), |(runs_offset, pixels_offset)| {
+            LineOffsets {
+                runs_offset,
+                pixels_offset,
+            }
+        }),
+        height as usize,
+    )(input)
+    .map_err(failure)?;
+
+    if palette_index as usize >= palettes.len() {
+        return Err((
+            palette_offset,
+            Reason::MissingPalette {
+                index: palette_index,
+                count: palettes.len(),
             },
-        ))
+        ));
     }
+    if width as u64 * height as u64 > MAX_FRAME_PIXELS {
+        return Err((offset, Reason::TooLarge { width, height }));
+    }
+    let IndexedPixels { indices, opaque } = pixels(file, offset, rows, width, height)?;
+
+    Ok(Frame {
+        width,
+        height,
+        center_x,
+        center_y,
+        unknown1,
+        unknown2,
+        name,
+        palette_index,
+        indices,
+        opaque,
+    })
 }
 
 fn palette(i: &[u8]) -> IResult<&[u8], Palette> {
@@ -182,14 +622,16 @@ fn palette(i: &[u8]) -> IResult<&[u8], Palette> {
 }
 
 fn header(input: &[u8]) -> IResult<&[u8], SpriteFileHeader> {
-    let (input, (_, _, _, num_frames, num_palettes, _)) =
-        tuple((tag("SPR\0"), le_u32, le_u32, le_u32, le_u32, le_u32))(input)?;
+    let (input, (_, unknown1, unknown2, num_frames, num_palettes, unknown3)) =
+        tuple((tag(MAGIC), le_u32, le_u32, le_u32, le_u32, le_u32))(input)?;
     let (input, palettes) = count(palette, num_palettes as usize)(input)?;
-    let (input, frame_offsets) = count(le_u32, num_frames as usize)(input)?;
+    let (input, frame_offsets) =
+        count(map(le_u32, |offset| offset as usize), num_frames as usize)(input)?;
 
     Ok((
         input,
         SpriteFileHeader {
+            unknown: [unknown1, unknown2, unknown3],
             palettes,
             frame_offsets,
         },
@@ -200,11 +642,318 @@ fn header(input: &[u8]) -> IResult<&[u8], SpriteFileHeader> {
 mod tests {
     use super::*;
     use mm_compression::test_utils::*;
+    use mm_compression::{compress, CompressOptions};
+    use std::io::Cursor;
+
+    /// Offset of the only frame in [sprite_file]
+    const FRAME_OFFSET: usize = 24 + 768 + 4;
+
+    /// Sprite file with single 2x2 frame: skip and red pixel in the
+    /// first row, red and green in the second
+    fn sprite_file() -> Vec<u8> {
+        let mut file = b"SPR\0".to_vec();
+        for value in [0u32, 0, 1, 1, 0] {
+            file.extend(value.to_le_bytes());
+        }
+        let mut palette = [0; 768];
+        palette[3..9].copy_from_slice(&[0xff, 0, 0, 0, 0xff, 0]);
+        file.extend(palette);
+        file.extend(0u32.to_le_bytes());
+
+        for value in [0u32, 2, 2, 1, 1] {
+            file.extend(value.to_le_bytes());
+        }
+        file.extend(b"tile\0\0\0\0");
+        // Palette index, unknown values, runs and pixels offsets of rows
+        for value in [0u32, 0, 0, 56, 60, 58, 61] {
+            file.extend(value.to_le_bytes());
+        }
+        file.extend([1, 1, 0, 2, 1, 1, 2]);
+        file
+    }
+
+    fn frame_error(file: &[u8]) -> (usize, usize, Reason) {
+        match Sprites::from_bytes(file).unwrap_err() {
+            SpriteError::InvalidFrame {
+                index,
+                offset,
+                reason,
+            } => (index, offset, reason),
+            error => panic!("unexpected error {:?}", error),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let sprites = Sprites::from_bytes(&sprite_file()).unwrap();
+        assert_eq!(1, sprites.frames.len());
+        let frame = &sprites.frames[0];
+        assert_eq!(
+            ("tile\0\0\0\0", 2, 2),
+            (&frame.name[..], frame.width, frame.height)
+        );
+        assert_eq!(0, frame.palette_index);
+        assert_eq!(vec![0, 1, 1, 2], frame.indices);
+        assert_eq!(vec![false, true, true, true], frame.opaque);
+        assert_eq!(
+            (None, Some(2)),
+            (frame.index_at(0, 0), frame.index_at(1, 1))
+        );
+        assert_eq!(
+            &[0, 0, 0, 0, 255, 0, 0, 255, 255, 0, 0, 255, 0, 255, 0, 255],
+            &sprites.to_rgba(frame).unwrap().as_raw()[..]
+        );
+
+        // Same frame with other palette
+        let mut palette = sprites.palettes[0].clone();
+        palette.swap(1, 2);
+        assert_eq!(
+            &[0, 0, 0, 0, 0, 255, 0, 255, 0, 255, 0, 255, 255, 0, 0, 255],
+            &frame.to_rgba(&palette).as_raw()[..]
+        );
+
+        let frame = Frame {
+            palette_index: 3,
+            ..frame.clone()
+        };
+        assert!(matches!(
+            sprites.to_rgba(&frame).unwrap_err(),
+            SpriteError::MissingPalette { index: 3, count: 1 }
+        ));
+    }
+
+    #[test]
+    fn test_from_bytes_and_reader() {
+        let file = sprite_file();
+        let obfuscated = compress(&file, &CompressOptions::default());
+        for sprites in [
+            Sprites::from_bytes(&file),
+            Sprites::from_bytes(&obfuscated),
+            Sprites::from_reader(Cursor::new(&file)),
+            Sprites::from_reader(&obfuscated[..]),
+        ] {
+            let sprites = sprites.unwrap();
+            assert_eq!(1, sprites.frames.len());
+            assert_eq!(2, sprites.frames[0].width);
+        }
+        assert!(matches!(
+            Sprites::from_bytes(b"not a sprite file").unwrap_err(),
+            SpriteError::InvalidHeader {
+                offset: 0,
+                reason: Reason::BadMagic
+            }
+        ));
+    }
+
+    #[test]
+    fn test_write() {
+        let mut sprites = Sprites::from_bytes(&sprite_file()).unwrap();
+        sprites.unknown = [1, 2, 3];
+        let mut palette = sprites.palettes[0].clone();
+        palette.reverse();
+        sprites.palettes.push(palette);
+        // Runs longer than 255 pixels, fully transparent and empty rows
+        let (width, height) = (600, 3);
+        let opaque: Vec<bool> = (0..width * height)
+            .map(|i| i < 300 || (i >= 2 * width && i % 7 != 0 && i % width < 520))
+            .collect();
+        sprites.frames.push(Frame {
+            width,
+            height,
+            center_x: -5,
+            center_y: 17,
+            unknown1: 3,
+            unknown2: 4,
+            name: "long".to_string(),
+            palette_index: 1,
+            indices: (0..width * height)
+                .zip(&opaque)
+                .map(|(i, &opaque)| if opaque { i as u8 } else { 0 })
+                .collect(),
+            opaque,
+        });
+        for (name, width, height) in [("empty", 0, 0), ("0x3", 0, 3), ("3x0", 3, 0)] {
+            sprites.frames.push(Frame {
+                width,
+                height,
+                name: name.to_string(),
+                indices: vec![],
+                opaque: vec![],
+                ..sprites.frames[0].clone()
+            });
+        }
+
+        let mut file = Vec::new();
+        sprites.write(&mut file).unwrap();
+        let written = Sprites::from_bytes(&file).unwrap();
+        assert_eq!([1, 2, 3], written.unknown);
+        assert_eq!(sprites.palettes, written.palettes);
+        let names: Vec<_> = written.frames.iter().map(|frame| &frame.name[..]).collect();
+        assert_eq!(
+            vec![
+                "tile\0\0\0\0",
+                "long\0\0\0\0",
+                "empty\0\0\0",
+                "0x3\0\0\0\0\0",
+                "3x0\0\0\0\0\0"
+            ],
+            names
+        );
+        for (frame, written) in sprites.frames.iter().zip(&written.frames) {
+            assert_eq!((frame.width, frame.height), (written.width, written.height));
+            assert_eq!(frame.palette_index, written.palette_index);
+            assert_eq!(frame.indices, written.indices);
+            assert_eq!(frame.opaque, written.opaque);
+        }
+        assert_eq!(sprites.frames[0], written.frames[0]);
+        assert_eq!(
+            (-5, 17, 3, 4),
+            (
+                written.frames[1].center_x,
+                written.frames[1].center_y,
+                written.frames[1].unknown1,
+                written.frames[1].unknown2
+            )
+        );
+
+        sprites.frames[1].name = "too long name".to_string();
+        let error = sprites.write(&mut Vec::new()).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, error.kind());
+
+        sprites.frames.truncate(1);
+        sprites.frames[0].width = 5000;
+        sprites.frames[0].height = 5000;
+        let error = sprites.write(&mut Vec::new()).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, error.kind());
+    }
+
+    #[test]
+    fn test_lazy() {
+        let mut file = sprite_file();
+        // Second frame pointing past the end of file
+        file[12] = 2;
+        file.splice(24 + 768 + 4..24 + 768 + 4, 1000u32.to_le_bytes());
+
+        let sprites = LazySprites::from_bytes(&file).unwrap();
+        assert_eq!(2, sprites.len());
+        assert!(sprites.frames.iter().all(|frame| frame.get().is_none()));
+        let frame = sprites.frame(0).unwrap();
+        assert!(std::ptr::eq(frame, sprites.frame(0).unwrap()));
+        assert!(matches!(
+            sprites.frame(1).unwrap_err(),
+            SpriteError::InvalidFrame { index: 1, .. }
+        ));
+        assert!(matches!(
+            sprites.frame(2).unwrap_err(),
+            SpriteError::MissingFrame { index: 2, count: 2 }
+        ));
+        assert!(matches!(
+            sprites.into_sprites().unwrap_err(),
+            SpriteError::InvalidFrame { index: 1, .. }
+        ));
+
+        let obfuscated = compress(&sprite_file(), &CompressOptions::default());
+        let sprites = LazySprites::from_reader(&obfuscated[..]).unwrap();
+        assert_eq!(2, sprites.frame(0).unwrap().width);
+        assert_eq!(1, sprites.into_sprites().unwrap().frames.len());
+    }
+
+    #[test]
+    fn test_parse_truncated() {
+        let file = sprite_file();
+        for length in 0..file.len() {
+            assert!(Sprites::from_bytes(&file[..length]).is_err(), "{length}");
+        }
+        match Sprites::from_bytes(&file[..100]).unwrap_err() {
+            SpriteError::InvalidHeader { offset, reason } => {
+                assert_eq!((100, Reason::PrematureEnd), (offset, reason))
+            }
+            error => panic!("unexpected error {:?}", error),
+        }
+        assert_eq!(
+            (0, FRAME_OFFSET + 8, Reason::PrematureEnd),
+            frame_error(&file[..FRAME_OFFSET + 10])
+        );
+        assert_eq!(
+            (0, file.len() - 1, Reason::PixelsPastEnd { row: 1 }),
+            frame_error(&file[..file.len() - 1])
+        );
+    }
+
+    #[test]
+    fn test_parse_corrupted() {
+        let mut file = sprite_file();
+        file[0] = b'X';
+        match Sprites::from_bytes(&file).unwrap_err() {
+            SpriteError::InvalidHeader { offset, reason } => {
+                assert_eq!((0, Reason::BadMagic), (offset, reason))
+            }
+            error => panic!("unexpected error {:?}", error),
+        }
+
+        let mut file = sprite_file();
+        file[FRAME_OFFSET + 28] = 5;
+        assert_eq!(
+            (
+                0,
+                FRAME_OFFSET + 28,
+                Reason::MissingPalette { index: 5, count: 1 }
+            ),
+            frame_error(&file)
+        );
+
+        let mut file = sprite_file();
+        file[FRAME_OFFSET + 20] = 0xff;
+        assert_eq!(
+            (0, FRAME_OFFSET + 20, Reason::InvalidName),
+            frame_error(&file)
+        );
+
+        let mut file = sprite_file();
+        file[FRAME_OFFSET + 4..FRAME_OFFSET + 8].copy_from_slice(&u32::MAX.to_le_bytes());
+        let (_, _, reason) = frame_error(&file);
+        assert!(matches!(reason, Reason::TooLarge { .. }));
+
+        // Runs of the second row start at the end of file
+        let mut file = sprite_file();
+        file[FRAME_OFFSET + 48] = 63;
+        assert_eq!(
+            (0, file.len(), Reason::RunsPastEnd { row: 1 }),
+            frame_error(&file)
+        );
+
+        let mut file = sprite_file();
+        file[FRAME_OFFSET + 44] = 200;
+        assert_eq!(
+            (0, FRAME_OFFSET + 200, Reason::RowOutOfBounds { row: 0 }),
+            frame_error(&file)
+        );
+
+        let mut file = sprite_file();
+        file[24 + 768] = 100;
+        assert_eq!(
+            (0, FRAME_OFFSET + 100, Reason::PrematureEnd),
+            frame_error(&file)
+        );
+    }
 
     #[test]
     #[ignore]
     fn test_load() {
         let f = File::open(test_file_path("Realms/Celtic/Forest/Terrain.spr")).unwrap();
-        Sprites::parse(f);
+        Sprites::parse(f).unwrap();
+    }
+
+    #[test]
+    #[ignore]
+    fn test_write_original() {
+        let original = test_file_contents("Realms/Celtic/Forest/Terrain.spr");
+        let sprites = Sprites::from_bytes(&original).unwrap();
+        let mut file = Vec::new();
+        sprites.write(&mut file).unwrap();
+        let written = Sprites::from_bytes(&file).unwrap();
+        assert_eq!(sprites.unknown, written.unknown);
+        assert_eq!(sprites.palettes, written.palettes);
+        assert_eq!(sprites.frames, written.frames);
     }
 }
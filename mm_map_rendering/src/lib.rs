@@ -1,6 +1,6 @@
-use image;
+use image::Pixel;
 use mm_file_formats::map_section::MapSection;
-use mm_file_formats::sprites::Sprites;
+use mm_file_formats::sprites::{Frame, LazySprites, SpriteError};
 use nalgebra::{Matrix2x3, SMatrix, Vector2, Vector3};
 use std::cmp;
 
@@ -24,8 +24,8 @@ pub struct RenderOptions {
     pub max_layer: u32,
 }
 
-impl RenderOptions {
-    pub fn default() -> Self {
+impl Default for RenderOptions {
+    fn default() -> Self {
         RenderOptions { max_layer: 255 }
     }
 }
@@ -77,9 +77,14 @@ fn map_rendering_order(
     })
 }
 
-fn blit(destination: &mut image::RgbaImage, source: &image::RgbaImage, pos: Vector2<i32>) {
-    for x in 0..source.width() as i32 {
-        for y in 0..source.height() as i32 {
+fn blit(
+    destination: &mut image::RgbaImage,
+    source: &Frame,
+    palette: &[image::Rgb<u8>],
+    pos: Vector2<i32>,
+) {
+    for x in 0..source.width as i32 {
+        for y in 0..source.height as i32 {
             let dest_x = x + pos.x;
             let dest_y = y + pos.y;
             if dest_x >= 0
@@ -87,13 +92,9 @@ fn blit(destination: &mut image::RgbaImage, source: &image::RgbaImage, pos: Vect
                 && dest_y >= 0
                 && dest_y < (destination.height() as i32)
             {
-                let src_pixel = source.get_pixel(x as u32, y as u32);
-                if src_pixel[3] != 0 {
-                    destination.put_pixel(
-                        (x + pos.x) as u32,
-                        (y + pos.y) as u32,
-                        src_pixel.clone(),
-                    );
+                if let Some(index) = source.index_at(x as u32, y as u32) {
+                    let src_pixel = palette[index as usize].to_rgba();
+                    destination.put_pixel((x + pos.x) as u32, (y + pos.y) as u32, src_pixel);
                 }
             }
         }
@@ -102,37 +103,34 @@ fn blit(destination: &mut image::RgbaImage, source: &image::RgbaImage, pos: Vect
 
 fn draw_tile(
     canvas: &mut image::RgbaImage,
-    sprites: &Sprites,
+    sprites: &LazySprites,
     tile_coordinates: TileCoordinates,
     tile_id: u16,
     origin: Vector2<i32>,
-) {
+) -> Result<(), SpriteError> {
     if tile_id == 0xffff || tile_id == 0x0000 {
-        return;
+        return Ok(());
     }
     let proj_tile_coordinates = project(tile_coordinates);
-    let sprite = &sprites.frames[tile_id as usize];
+    let sprite = sprites.frame(tile_id as usize)?;
     let target_coordinates =
         proj_tile_coordinates - Vector2::new(sprite.center_x, sprite.center_y) + origin;
 
-    blit(
-        canvas,
-        &sprites.frames[tile_id as usize].image,
-        target_coordinates,
-    )
+    blit(canvas, sprite, sprites.palette(sprite)?, target_coordinates);
+    Ok(())
 }
 
 pub fn render_map_section(
     map_section: &MapSection,
-    sprites: &Sprites,
+    sprites: &LazySprites,
     options: &RenderOptions,
-) -> image::RgbaImage {
+) -> Result<image::RgbaImage, SpriteError> {
     let canvas_size = CanvasSize::for_map_section(map_section);
     let mut canvas = image::RgbaImage::new(canvas_size.size.x, canvas_size.size.y);
     for tile_coordinates in map_rendering_order(map_section, options.max_layer) {
         draw_tile(
             &mut canvas,
-            &sprites,
+            sprites,
             tile_coordinates,
             map_section
                 .tile_at(
@@ -142,9 +140,9 @@ pub fn render_map_section(
                 )
                 .id,
             canvas_size.center,
-        );
+        )?;
     }
-    canvas
+    Ok(canvas)
 }
 
 #[cfg(test)]
@@ -152,7 +150,6 @@ mod tests {
     use super::*;
     use mm_compression::test_utils::*;
     use mm_file_formats::map_section::MapSection;
-    use mm_file_formats::sprites::Sprites;
     use std::fs::File;
 
     #[test]
@@ -162,8 +159,10 @@ mod tests {
             "Realms/Celtic/Forest/CFSec10.map",
         ))
         .unwrap();
-        let sprites =
-            Sprites::parse(File::open(test_file_path("Realms/Celtic/Forest/Terrain.spr")).unwrap());
-        render_map_section(&map_section, &sprites, &RenderOptions::default());
+        let sprites = LazySprites::from_reader(
+            File::open(test_file_path("Realms/Celtic/Forest/Terrain.spr")).unwrap(),
+        )
+        .unwrap();
+        render_map_section(&map_section, &sprites, &RenderOptions::default()).unwrap();
     }
 }
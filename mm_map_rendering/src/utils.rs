@@ -1,9 +1,7 @@
 use crate::{render_map_section, MapSection, RenderOptions};
 use anyhow::{anyhow, Result};
-use mm_compression::read_decompressed;
-use mm_file_formats::sprites::Sprites;
+use mm_file_formats::sprites::LazySprites;
 use std::fs::File;
-use std::mem;
 use std::path::{Path, PathBuf};
 use std::sync::RwLock;
 
@@ -11,7 +9,7 @@ pub struct RendererCache {
     section_path: PathBuf,
     sprites_path: PathBuf,
     map_section: MapSection,
-    sprites: Sprites,
+    sprites: LazySprites,
 }
 
 pub struct Renderer {
@@ -21,7 +19,7 @@ pub struct Renderer {
 
 pub fn load_sprites_and_map_section_cached<
     L1: Fn(&Path) -> Result<MapSection>,
-    L2: Fn(&Path) -> Result<Sprites>,
+    L2: Fn(&Path) -> Result<LazySprites>,
 >(
     cache: Option<RendererCache>,
     section_path: &Path,
@@ -68,8 +66,8 @@ impl Renderer {
     fn section_path(&self, map_group: &str, map_section: &str) -> PathBuf {
         self.mm_path
             .join("Realms")
-            .join(&map_group)
-            .join(&map_section)
+            .join(map_group)
+            .join(map_section)
             .with_extension("map")
     }
 
@@ -79,7 +77,7 @@ impl Renderer {
         map_section: &str,
         options: &RenderOptions,
     ) -> Result<image::RgbaImage> {
-        let map_section_path_1 = self.section_path(&map_group, &map_section);
+        let map_section_path_1 = self.section_path(map_group, map_section);
         let sprites_path = map_section_path_1
             .parent()
             .ok_or(anyhow!("Can't get parent of map section path"))?
@@ -89,20 +87,18 @@ impl Renderer {
             .cache
             .write()
             .map_err(|e| anyhow!("Can't unlock cache for writing: {}", e))?;
-        let old_cache_contents = mem::replace(&mut *cache_writer, None);
+        let old_cache_contents = cache_writer.take();
         let new_cache_contents = load_sprites_and_map_section_cached(
             old_cache_contents,
             &map_section_path_1,
             &sprites_path,
             |map_section_path| {
                 eprintln!("Loading map section {:?}", &map_section_path);
-                Ok(MapSection::from_contents(read_decompressed(
-                    map_section_path,
-                )?)?)
+                Ok(MapSection::read(map_section_path)?)
             },
             |sprites_path| {
                 eprintln!("Loading sprites {:?}", &sprites_path);
-                Ok(Sprites::parse(File::open(sprites_path)?))
+                Ok(LazySprites::from_reader(File::open(sprites_path)?)?)
             },
         )?;
 
@@ -113,6 +109,6 @@ impl Renderer {
             options,
         );
         *cache_writer = Some(new_cache_contents);
-        Ok(image)
+        Ok(image?)
     }
 }
@@ -1,6 +1,6 @@
 use mm_compression::read_decompressed;
 use mm_file_formats::map_section::MapSection;
-use mm_file_formats::sprites::Sprites;
+use mm_file_formats::sprites::LazySprites;
 use mm_map_rendering::{render_map_section, RenderOptions};
 use std::env;
 use std::fs::File;
@@ -20,7 +20,9 @@ fn main() {
         read_decompressed(map_section_path).expect("Couldn't deobfuscate map section"),
     )
     .expect("Couldn't parse map section");
-    let sprites = Sprites::parse(File::open(sprites_path).unwrap());
-    let image = render_map_section(&map_section, &sprites, &RenderOptions::default());
+    let sprites = LazySprites::from_reader(File::open(sprites_path).unwrap())
+        .expect("Couldn't parse sprites");
+    let image = render_map_section(&map_section, &sprites, &RenderOptions::default())
+        .expect("Couldn't render map section");
     image.save(&args[2]).unwrap();
 }
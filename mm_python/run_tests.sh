@@ -0,0 +1,16 @@
+#!/bin/sh
+# Builds extension module and runs Python tests with it, without
+# installing. `maturin develop` installs it into virtualenv instead.
+# The crate isn't in the workspace, so it's built in its own target
+# directory.
+set -e
+cd "$(dirname "$0")"
+cargo build
+module_dir=$(mktemp -d)
+trap 'rm -rf "$module_dir"' EXIT
+for library in target/debug/libmm_python.so target/debug/libmm_python.dylib; do
+    if [ -f "$library" ]; then
+        cp "$library" "$module_dir/mm_python.so"
+    fi
+done
+PYTHONPATH="$module_dir" python3 -m unittest discover -s tests -v
@@ -0,0 +1,300 @@
+//! Python bindings for Magic & Mayhem file formats
+//!
+//! Built as `mm_python` extension module, with `maturin develop` or
+//! see `run_tests.sh`. Functions taking file contents accept `bytes`
+//! and return `bytes`, sprite frames support numpy array interface.
+
+use std::fs::File;
+use std::path::PathBuf;
+
+use image::Rgb;
+use mm_compression::{
+    ChecksumMode, CompressOptions, Compression, CompressionLevel, DecompressOptions,
+};
+use mm_file_formats::map_section;
+use mm_file_formats::sprites::{self, SpriteError};
+use pyo3::create_exception;
+use pyo3::exceptions::{PyIndexError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+
+create_exception!(
+    mm_python,
+    DecodeError,
+    PyValueError,
+    "File can't be decompressed or parsed"
+);
+
+fn decode_error(error: impl ToString) -> PyErr {
+    DecodeError::new_err(error.to_string())
+}
+
+/// Deobfuscates and decompresses `data`
+///
+/// With `lenient`, data is returned even if checksums don't match.
+#[pyfunction]
+#[pyo3(signature = (data, lenient = false))]
+fn decompress<'py>(py: Python<'py>, data: &[u8], lenient: bool) -> PyResult<Bound<'py, PyBytes>> {
+    let options = DecompressOptions {
+        checksums: if lenient {
+            ChecksumMode::Lenient
+        } else {
+            ChecksumMode::Strict
+        },
+        ..Default::default()
+    };
+    let decompressed = py
+        .detach(|| mm_compression::decompress_with_options(data, &options))
+        .map_err(decode_error)?;
+    Ok(PyBytes::new(py, &decompressed.data))
+}
+
+/// Compresses and obfuscates `data` into format readable by the game
+///
+/// `compression` is one of "lzss" and "uncompressed", `level`
+/// one of "fast" and "optimal".
+#[pyfunction]
+#[pyo3(signature = (data, seed = 0, compression = "lzss", level = "fast"))]
+fn compress<'py>(
+    py: Python<'py>,
+    data: &[u8],
+    seed: u32,
+    compression: &str,
+    level: &str,
+) -> PyResult<Bound<'py, PyBytes>> {
+    let options = CompressOptions {
+        seed,
+        compression: match compression {
+            "lzss" => Compression::LZSS,
+            "uncompressed" => Compression::Uncompressed,
+            _ => {
+                let message = format!("unknown compression type {:?}", compression);
+                return Err(PyValueError::new_err(message));
+            }
+        },
+        level: match level {
+            "fast" => CompressionLevel::Fast,
+            "optimal" => CompressionLevel::Optimal,
+            _ => {
+                let message = format!("unknown compression level {:?}", level);
+                return Err(PyValueError::new_err(message));
+            }
+        },
+    };
+    let compressed = py.detach(|| mm_compression::compress(data, &options));
+    Ok(PyBytes::new(py, &compressed))
+}
+
+/// Map section, from obfuscated file as in the game or already
+/// decompressed contents
+#[pyclass(frozen)]
+struct MapSection {
+    section: map_section::MapSection,
+}
+
+#[pymethods]
+impl MapSection {
+    #[new]
+    fn new(data: Vec<u8>) -> PyResult<Self> {
+        let contents = mm_compression::decode_game_file(data)
+            .map_err(decode_error)?
+            .contents;
+        let section = map_section::MapSection::from_contents(contents).map_err(decode_error)?;
+        Ok(MapSection { section })
+    }
+
+    #[staticmethod]
+    fn read(path: PathBuf) -> PyResult<Self> {
+        let section = map_section::MapSection::read(path).map_err(decode_error)?;
+        Ok(MapSection { section })
+    }
+
+    /// Size as `(x, y, z)`
+    #[getter]
+    fn size(&self) -> (u32, u32, u32) {
+        (
+            self.section.size_x,
+            self.section.size_y,
+            self.section.size_z,
+        )
+    }
+
+    /// Id of tile at given position
+    fn tile_at(&self, x: u32, y: u32, z: u32) -> PyResult<u16> {
+        let section = &self.section;
+        if x >= section.size_x || y >= section.size_y || z >= section.size_z {
+            let message = format!("tile {}, {}, {} is out of map section", x, y, z);
+            return Err(PyIndexError::new_err(message));
+        }
+        Ok(section.tile_at(x, y, z).id)
+    }
+}
+
+/// Sprite frame with RGBA pixels
+///
+/// `numpy.asarray(frame)` gives `(height, width, 4)` array of
+/// `uint8`.
+#[pyclass(frozen)]
+struct Frame {
+    #[pyo3(get)]
+    name: String,
+    #[pyo3(get)]
+    width: u32,
+    #[pyo3(get)]
+    height: u32,
+    #[pyo3(get)]
+    center_x: i32,
+    #[pyo3(get)]
+    center_y: i32,
+    /// Pixels row by row, 4 bytes each
+    #[pyo3(get)]
+    rgba: Py<PyBytes>,
+}
+
+#[pymethods]
+impl Frame {
+    #[getter]
+    fn __array_interface__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let interface = PyDict::new(py);
+        interface.set_item("version", 3)?;
+        interface.set_item("shape", (self.height, self.width, 4))?;
+        interface.set_item("typestr", "|u1")?;
+        interface.set_item("data", self.rgba.bind(py))?;
+        Ok(interface)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("<Frame {:?} {}x{}>", self.name, self.width, self.height)
+    }
+}
+
+fn sprite_error(error: SpriteError) -> PyErr {
+    match error {
+        SpriteError::ReadError(error) => error.into(),
+        error => decode_error(error),
+    }
+}
+
+/// Sprite sheet, such as `Terrain.spr`, from obfuscated or plain
+/// contents
+#[pyclass(frozen)]
+struct Sprites {
+    #[pyo3(get)]
+    frames: Vec<Py<Frame>>,
+}
+
+impl Sprites {
+    fn from_sprites(py: Python, sprites: sprites::Sprites) -> PyResult<Self> {
+        let frames = sprites
+            .frames
+            .iter()
+            .map(|frame| {
+                let rgba = sprites.to_rgba(frame).map_err(sprite_error)?;
+                let rgba = PyBytes::new(py, rgba.as_raw()).unbind();
+                let frame = Frame {
+                    name: frame.name.clone(),
+                    width: frame.width,
+                    height: frame.height,
+                    center_x: frame.center_x,
+                    center_y: frame.center_y,
+                    rgba,
+                };
+                Py::new(py, frame)
+            })
+            .collect::<PyResult<_>>()?;
+        Ok(Sprites { frames })
+    }
+}
+
+#[pymethods]
+impl Sprites {
+    #[new]
+    fn new(py: Python, data: &[u8]) -> PyResult<Self> {
+        let sprites = sprites::Sprites::from_bytes(data).map_err(sprite_error)?;
+        Self::from_sprites(py, sprites)
+    }
+
+    #[staticmethod]
+    fn read(py: Python, path: PathBuf) -> PyResult<Self> {
+        let sprites = sprites::Sprites::from_reader(File::open(path)?).map_err(sprite_error)?;
+        Self::from_sprites(py, sprites)
+    }
+
+    fn __len__(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Frame at `index`, negative ones counting from the end
+    fn __getitem__(&self, py: Python, index: isize) -> PyResult<Py<Frame>> {
+        let index = if index < 0 {
+            index.checked_add_unsigned(self.frames.len())
+        } else {
+            Some(index)
+        };
+        index
+            .and_then(|index| usize::try_from(index).ok())
+            .and_then(|index| self.frames.get(index))
+            .map(|frame| frame.clone_ref(py))
+            .ok_or_else(|| PyIndexError::new_err("frame index out of range"))
+    }
+}
+
+/// Frame given to [write_sprites]
+type FrameTuple = (String, u32, u32, i32, i32, Vec<Option<u8>>);
+
+/// Encodes sprite file, not obfuscated, with single `palette` of 256
+/// `(r, g, b)` colours
+///
+/// `frames` are `(name, width, height, center_x, center_y, pixels)`,
+/// with palette index of each pixel row by row in `pixels`, `None`
+/// for transparent ones.
+#[pyfunction]
+fn write_sprites<'py>(
+    py: Python<'py>,
+    palette: Vec<(u8, u8, u8)>,
+    frames: Vec<FrameTuple>,
+) -> PyResult<Bound<'py, PyBytes>> {
+    let palette = palette
+        .into_iter()
+        .map(|(r, g, b)| Rgb([r, g, b]))
+        .collect();
+    let frames = frames
+        .into_iter()
+        .map(
+            |(name, width, height, center_x, center_y, pixels)| sprites::Frame {
+                width,
+                height,
+                center_x,
+                center_y,
+                unknown1: 0,
+                unknown2: 0,
+                name,
+                palette_index: 0,
+                indices: pixels.iter().map(|index| index.unwrap_or(0)).collect(),
+                opaque: pixels.iter().map(Option::is_some).collect(),
+            },
+        )
+        .collect();
+    let sprites = sprites::Sprites {
+        unknown: [0; 3],
+        palettes: vec![palette],
+        frames,
+    };
+    let mut output = Vec::new();
+    sprites
+        .write(&mut output)
+        .map_err(|error| PyValueError::new_err(error.to_string()))?;
+    Ok(PyBytes::new(py, &output))
+}
+
+#[pymodule]
+fn mm_python(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(decompress, m)?)?;
+    m.add_function(wrap_pyfunction!(compress, m)?)?;
+    m.add_function(wrap_pyfunction!(write_sprites, m)?)?;
+    m.add_class::<MapSection>()?;
+    m.add_class::<Frame>()?;
+    m.add_class::<Sprites>()?;
+    m.add("DecodeError", m.py().get_type::<DecodeError>())?;
+    Ok(())
+}
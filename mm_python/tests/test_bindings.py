@@ -0,0 +1,126 @@
+import os
+import struct
+import tempfile
+import unittest
+
+import mm_python
+
+try:
+    import numpy
+except ImportError:
+    numpy = None
+
+TILES_OFFSET = 0x4C
+TILE_BYTES = 12
+
+
+def map_section_contents(size_x, size_y, size_z):
+    contents = bytearray(struct.pack("<4I", 6, size_x, size_y, size_z))
+    contents.extend(bytes(TILES_OFFSET - len(contents)))
+    for index in range(size_x * size_y * size_z):
+        contents.extend(struct.pack("<H", index + 100).ljust(TILE_BYTES, b"\0"))
+    return bytes(contents)
+
+
+def sprite_file():
+    """Single 2x2 frame: skip and red in the first row, red and green
+    in the second"""
+    palette = [(0, 0, 0), (255, 0, 0), (0, 255, 0)] + [(0, 0, 0)] * 253
+    return mm_python.write_sprites(palette, [("tile", 2, 2, 1, 1, [None, 1, 1, 2])])
+
+
+class CompressionTest(unittest.TestCase):
+    def test_roundtrip(self):
+        data = b"The quick brown fox jumps over the lazy dog" * 20
+        for compression in ["lzss", "uncompressed"]:
+            compressed = mm_python.compress(data, seed=42, compression=compression)
+            self.assertEqual(struct.pack("<I", 42), compressed[:4])
+            self.assertEqual(data, mm_python.decompress(compressed))
+
+    def test_invalid_options(self):
+        with self.assertRaises(ValueError):
+            mm_python.compress(b"data", compression="zip")
+        for level in ["best", "faithful"]:
+            with self.assertRaises(ValueError):
+                mm_python.compress(b"data", level=level)
+
+    def test_decompress_corrupt(self):
+        compressed = bytearray(mm_python.compress(bytes(100), compression="uncompressed"))
+        compressed[30] ^= 0xFF
+        with self.assertRaises(mm_python.DecodeError):
+            mm_python.decompress(bytes(compressed))
+        mm_python.decompress(bytes(compressed), lenient=True)
+
+
+class MapSectionTest(unittest.TestCase):
+    def test_tiles(self):
+        contents = map_section_contents(3, 2, 2)
+        for data in [contents, mm_python.compress(contents)]:
+            section = mm_python.MapSection(data)
+            self.assertEqual((3, 2, 2), section.size)
+            self.assertEqual(100, section.tile_at(0, 0, 0))
+            self.assertEqual(100 + 6 + 3 + 2, section.tile_at(2, 1, 1))
+            with self.assertRaises(IndexError):
+                section.tile_at(3, 0, 0)
+
+    def test_invalid(self):
+        with self.assertRaises(mm_python.DecodeError):
+            mm_python.MapSection(b"not a map section")
+
+
+class SpritesTest(unittest.TestCase):
+    def setUp(self):
+        directory = tempfile.TemporaryDirectory()
+        self.addCleanup(directory.cleanup)
+        self.path = os.path.join(directory.name, "Test.spr")
+        with open(self.path, "wb") as f:
+            f.write(sprite_file())
+
+    def test_frames(self):
+        sprites = mm_python.Sprites.read(self.path)
+        self.assertEqual(1, len(sprites))
+        frame = sprites[0]
+        self.assertEqual(("tile\0\0\0\0", 2, 2), (frame.name, frame.width, frame.height))
+        self.assertEqual(
+            bytes([0, 0, 0, 0, 255, 0, 0, 255, 255, 0, 0, 255, 0, 255, 0, 255]),
+            frame.rgba,
+        )
+        interface = frame.__array_interface__
+        self.assertEqual((2, 2, 4), interface["shape"])
+        self.assertEqual("|u1", interface["typestr"])
+        self.assertIs(frame, sprites[-1])
+        for index in [1, -2]:
+            with self.assertRaises(IndexError):
+                sprites[index]
+
+    def test_read_malformed(self):
+        for data in [sprite_file()[:100], b"SPR\0" + b"\xff" * 60]:
+            with open(self.path, "wb") as f:
+                f.write(data)
+            with self.assertRaises(mm_python.DecodeError):
+                mm_python.Sprites.read(self.path)
+
+    def test_from_bytes(self):
+        for data in [sprite_file(), mm_python.compress(sprite_file())]:
+            sprites = mm_python.Sprites(data)
+            self.assertEqual(1, len(sprites))
+            self.assertEqual(16, len(sprites[0].rgba))
+        with self.assertRaises(mm_python.DecodeError):
+            mm_python.Sprites(sprite_file()[:100])
+
+    def test_write_invalid(self):
+        with self.assertRaises(ValueError):
+            mm_python.write_sprites([(0, 0, 0)] * 255, [])
+        with self.assertRaises(ValueError):
+            mm_python.write_sprites([(0, 0, 0)] * 256, [("tile", 2, 2, 0, 0, [1])])
+
+    @unittest.skipIf(numpy is None, "numpy is not installed")
+    def test_numpy(self):
+        frame = mm_python.Sprites.read(self.path)[0]
+        array = numpy.asarray(frame)
+        self.assertEqual((2, 2, 4), array.shape)
+        self.assertEqual([0, 255, 0, 255], list(array[1, 1]))
+
+
+if __name__ == "__main__":
+    unittest.main()